@@ -1,18 +1,29 @@
 use std::fs;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
 use ln_regtest_rs::bitcoin_client::BitcoinClient;
 use ln_regtest_rs::bitcoind::Bitcoind;
 use ln_regtest_rs::cln::Clnd;
+use ln_regtest_rs::ln_client::cln_client::{InvoiceBuilder, MIN_RENEPAY_VERSION};
+use ln_regtest_rs::ln_client::lnd_client::cln_scid_to_lnd_u64;
+use ln_regtest_rs::ln_client::types::{Balance, ChannelAcceptorPolicy};
 use ln_regtest_rs::ln_client::ClnClient;
 use ln_regtest_rs::ln_client::LightningClient;
 use ln_regtest_rs::ln_client::LndClient;
 use ln_regtest_rs::lnd::Lnd;
+use ln_regtest_rs::network::RegtestNetwork;
+use ln_regtest_rs::InvoiceStatus;
+use ln_regtest_rs::RegtestError;
 use tempfile::tempdir;
 use tracing_subscriber::EnvFilter;
 
+/// HTLCs CLN accepts from LND per channel
+const CLN_MAX_CONCURRENT_HTLCS: u32 = 5;
+
 fn create_wallet(bitcoind: &mut BitcoinClient) -> Result<()> {
     bitcoind.create_wallet().ok();
     bitcoind.load_wallet()?;
@@ -20,6 +31,18 @@ fn create_wallet(bitcoind: &mut BitcoinClient) -> Result<()> {
     Ok(())
 }
 
+/// Fee paid for a lightning payment of `amount_msat` from the balance change
+fn fee_paid_msat(before: &Balance, after: &Balance, amount_msat: u64) -> Result<u64> {
+    before
+        .ln
+        .checked_sub(after.ln)
+        .and_then(|sent| sent.checked_sub(amount_msat))
+        .ok_or(anyhow!(
+            "Balance did not decrease by at least {} msat",
+            amount_msat
+        ))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let default_filter = "debug";
@@ -36,8 +59,6 @@ async fn main() -> Result<()> {
     let btc_dir = temp_dir.path().join("btc_one");
     fs::create_dir_all(&btc_dir)?;
 
-    let bitcoind_addr = PathBuf::from_str("127.0.0.1:18443")?;
-
     let zmq_raw_block = "tcp://127.0.0.1:28332";
     let zmq_raw_tx = "tcp://127.0.0.1:28333";
 
@@ -45,21 +66,23 @@ async fn main() -> Result<()> {
     let btc_rpc_password = "testpassword".to_string();
     let mut bitcoind = Bitcoind::new(
         btc_dir.clone(),
-        bitcoind_addr.clone(),
         btc_rpc_user.clone(),
         btc_rpc_password.clone(),
+        18443,
         zmq_raw_block.to_string(),
         zmq_raw_tx.to_string(),
+        None,
+        true,
     );
 
     println!("Starting bitcoind");
-    bitcoind.start_bitcoind()?;
+    bitcoind.start_bitcoind().await?;
     println!("Started bitcoind");
 
     println!("Creating mining client");
     let mut bitcoin_client_mining = BitcoinClient::new(
         "Minting_wallet".to_string(),
-        bitcoind_addr.clone(),
+        bitcoind.rpc_url().into(),
         None,
         Some(btc_rpc_user.clone()),
         Some(btc_rpc_password.clone()),
@@ -68,7 +91,7 @@ async fn main() -> Result<()> {
     println!("Creating spending client");
     let mut bitcoin_client_spending = BitcoinClient::new(
         "spending_wallet".to_string(),
-        bitcoind_addr,
+        bitcoind.rpc_url().into(),
         None,
         Some(btc_rpc_user.clone()),
         Some(btc_rpc_password.clone()),
@@ -112,6 +135,10 @@ async fn main() -> Result<()> {
         cln_one_addr.clone(),
         btc_rpc_user.clone(),
         btc_rpc_password.clone(),
+        bitcoind.rpc_port(),
+        Some(CLN_MAX_CONCURRENT_HTLCS),
+        vec![],
+        None,
     );
     // Start CLN One
     clnd.start_clnd().map_err(|err| {
@@ -120,19 +147,19 @@ async fn main() -> Result<()> {
     })?;
     tracing::info!("CLN Started");
 
-    let cln_client = ClnClient::new(cln_one_dir, None).await?;
+    let cln_client = ClnClient::new(clnd.data_dir().clone(), Some(clnd.rpc_path())).await?;
 
-    cln_client.wait_chain_sync().await?;
+    cln_client.wait_chain_sync(None).await?;
     tracing::info!("Cln client completed chain sync");
 
     // Fund CLN one
-    let cln_one_address = cln_client.get_new_onchain_address().await.unwrap();
+    let cln_one_address = cln_client.get_new_onchain_address(None).await.unwrap();
     println!("CLN Address: {}", cln_one_address);
 
     bitcoin_client_spending.send_to_address(&cln_one_address, 3_000_000)?;
     // CLN doesn't seem to see the funds unless 100 blocks are generated
     bitcoin_client_mining.generate_blocks(&mine_to_address, 100)?;
-    cln_client.wait_chain_sync().await?;
+    cln_client.wait_chain_sync(None).await?;
 
     let bal = cln_client.balance().await?;
 
@@ -151,31 +178,44 @@ async fn main() -> Result<()> {
         lnd_rpc_listen,
         btc_rpc_user,
         btc_rpc_password,
+        bitcoind.rpc_port(),
         zmq_raw_block.to_string(),
         zmq_raw_tx.to_string(),
+        Some(lnd_dir.join("lnd.log")),
+        None,
+        false,
     );
 
-    lnd.start_lnd()?;
+    lnd.start_lnd().await?;
     tracing::info!("LND Started");
 
-    let cert_file = lnd_dir.join("tls.cert");
-    let macaroon_file = lnd_dir.join("data/chain/bitcoin/regtest/admin.macaroon");
+    let cert_file = lnd.tls_cert_path();
+    let macaroon_file = lnd.admin_macaroon_path();
 
     let lnd_addr = "https://127.0.0.1:10009".to_string();
 
     let lnd_client = LndClient::new(lnd_addr, cert_file, macaroon_file).await?;
     tracing::info!("LND Client created");
 
-    lnd_client.wait_chain_sync().await?;
+    lnd_client.wait_chain_sync(None).await?;
     tracing::info!("LND Client completed chain sync");
 
     // Fund LND
-    let lnd_address = lnd_client.get_new_onchain_address().await?;
+    let lnd_address = lnd_client.get_new_onchain_address(None).await?;
     bitcoin_client_spending.send_to_address(&lnd_address, 3_000_000)?;
     bitcoin_client_mining.generate_blocks(&mine_to_address, 10)?;
 
-    lnd_client.wait_chain_sync().await?;
-    cln_client.wait_chain_sync().await?;
+    lnd_client.wait_chain_sync(None).await?;
+    cln_client.wait_chain_sync(None).await?;
+
+    let lnd_utxos = lnd_client.list_utxos().await?;
+    assert!(lnd_utxos
+        .iter()
+        .any(|utxo| utxo.address == lnd_address && utxo.spendable));
+    let cln_utxos = cln_client.list_utxos().await?;
+    assert!(cln_utxos
+        .iter()
+        .any(|utxo| utxo.address == cln_one_address && utxo.confirmations > 100));
 
     // Get lnd info
 
@@ -194,39 +234,459 @@ async fn main() -> Result<()> {
         .await
         .unwrap();
 
+    // Both implementations require option_static_remotekey
+    let cln_features = lnd_client.get_peer_features(&cln_pubkey).await?;
+    assert!(cln_features.contains_key(&12) || cln_features.contains_key(&13));
+
     lnd_client
-        .open_channel(1_500_000, &cln_pubkey, Some(500_000))
+        .open_channel(1_500_000, &cln_pubkey, Some(500_000), true, None, None)
         .await
         .unwrap();
 
     bitcoin_client_mining.generate_blocks(&mine_to_address, 10)?;
 
-    lnd_client.wait_chain_sync().await?;
-    cln_client.wait_chain_sync().await?;
+    lnd_client.wait_chain_sync(None).await?;
+    cln_client.wait_chain_sync(None).await?;
 
-    lnd_client.wait_channels_active().await?;
+    lnd_client.wait_channels_active(None).await?;
+
+    let channel_backup = lnd_client.export_channel_backup().await?;
+    assert!(!channel_backup.is_empty());
 
     let lnd_balance = lnd_client.balance().await?;
 
     println!("{:?}", lnd_balance);
 
-    let bolt11 = cln_client.create_invoice(Some(1000)).await?;
+    // Both nodes were funded with 3_000_000 sat, LND pushed 500_000 sat to CLN
+    lnd_client.assert_min_onchain_balance(1_000_000).await?;
+    cln_client.assert_min_onchain_balance(2_500_000).await?;
+    lnd_client.assert_min_ln_balance(900_000_000).await?;
+    cln_client.assert_min_ln_balance(500_000_000).await?;
+    assert!(matches!(
+        lnd_client.assert_min_ln_balance(1_500_000_000).await,
+        Err(RegtestError::AssertionFailed { .. })
+    ));
+    assert!(matches!(
+        cln_client.assert_min_onchain_balance(3_500_000).await,
+        Err(RegtestError::AssertionFailed { .. })
+    ));
+
+    let bolt11 = cln_client.create_invoice(Some(1_000_000), None).await?;
+
+    let preimage = lnd_client.pay_invoice(bolt11, None).await?;
+
+    let described_bolt11 = InvoiceBuilder::new()
+        .amount_msat(50_000)
+        .description("regtest invoice")
+        .expiry_secs(600)
+        .build(&cln_client)
+        .await?;
+    let decoded = lnd_client.decode_invoice(&described_bolt11).await?;
+    assert_eq!(decoded.amount_msat, Some(50_000));
+    assert_eq!(decoded.description.as_deref(), Some("regtest invoice"));
+    assert_eq!(
+        decoded.payee_pubkey,
+        Some(cln_client.get_node_pubkey().await?)
+    );
+    lnd_client.pay_invoice(described_bolt11, None).await?;
+
+    let lnd_bolt11 = lnd_client.create_invoice(Some(1_000_000), None).await?;
+
+    let cln_preimage = cln_client.pay_invoice(lnd_bolt11, None).await?;
+
+    // A zero fee limit fails a payment that has to pay the hub for routing
+    let network = RegtestNetwork::with_routing_node(&bitcoind, &bitcoin_client_mining).await?;
+    let bob_pubkey = network.bob().get_node_pubkey().await?;
+
+    // Alice only routes to Bob once the Hub -> Bob channel has been gossiped
+    let mut route = None;
+    for _ in 0..60 {
+        match network.alice().get_route(&bob_pubkey, 10_000_000).await {
+            Ok(hops) => {
+                route = Some(hops);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(2)).await,
+        }
+    }
+    let route = route.ok_or(anyhow!("Alice has no route to Bob"))?;
+    assert!(route.iter().map(|hop| hop.fee_msat).sum::<u64>() > 0);
 
-    let preimage = lnd_client.pay_invoice(bolt11).await?;
+    let fee_bolt11 = network.bob().create_invoice(Some(10_000_000), None).await?;
+    let err = network
+        .alice()
+        .pay_invoice_expecting_failure(&fee_bolt11, Some(0))
+        .await?;
+    println!("cln expected payment failure: {}", err);
+    assert!(err.to_string().to_lowercase().contains("fee"));
 
-    let lnd_bolt11 = lnd_client.create_invoice(Some(1000)).await?;
+    network.alice().pay_invoice(fee_bolt11, None).await?;
 
-    let cln_preimage = cln_client.pay_invoice(lnd_bolt11).await?;
+    // Amountless invoice paid with an explicit amount
+    let amountless_bolt11 = cln_client.create_invoice(None, Some(600)).await?;
+    lnd_client
+        .pay_invoice_with_amount(amountless_bolt11, 10_000, Some(1_000))
+        .await?;
 
     println!("preimage: {}", preimage);
     println!("cln preimage: {}", cln_preimage);
 
+    let payment_hash = sha256::Hash::hash(&ln_regtest_rs::hex::decode(&preimage)?).to_byte_array();
+    let payment_status = lnd_client
+        .wait_for_payment(
+            &ln_regtest_rs::hex::encode(payment_hash),
+            Duration::from_secs(30),
+        )
+        .await?;
+    assert_eq!(payment_status, InvoiceStatus::Paid);
     cln_client
-        .open_channel(1_500_000, &lnd_pubkey, None)
+        .wait_for_invoice_paid(
+            &ln_regtest_rs::hex::encode(payment_hash),
+            Duration::from_secs(30),
+        )
+        .await?;
+
+    // Keysend from LND to CLN
+    let cln_balance_before = cln_client.balance().await?;
+    let keysend_preimage = lnd_client.keysend(&cln_pubkey, 10_000).await?;
+    let cln_balance_after = cln_client.balance().await?;
+    println!("lnd keysend preimage: {}", keysend_preimage);
+    assert_eq!(cln_balance_after.ln, cln_balance_before.ln + 10_000);
+
+    // CLN pays LND hold invoice which LND settles once accepted
+    let hold_preimage = [
+        uuid::Uuid::new_v4().into_bytes(),
+        uuid::Uuid::new_v4().into_bytes(),
+    ]
+    .concat();
+    let hold_payment_hash = sha256::Hash::hash(&hold_preimage).to_byte_array();
+
+    let hold_bolt11 = lnd_client
+        .create_hold_invoice(1_000_000, &hold_payment_hash)
+        .await?;
+
+    let cln_paying_client = ClnClient::new(cln_one_dir, None).await?;
+    let hold_payment =
+        tokio::spawn(async move { cln_paying_client.pay_invoice(hold_bolt11, None).await });
+
+    let hold_payment_hash = ln_regtest_rs::hex::encode(hold_payment_hash);
+    lnd_client
+        .wait_for_invoice_accepted(&hold_payment_hash, Duration::from_secs(60))
         .await?;
+
+    lnd_client.settle_hold_invoice(&hold_preimage).await?;
+    hold_payment.await??;
+
+    assert_eq!(
+        lnd_client
+            .check_incoming_payment_status(&hold_payment_hash)
+            .await?,
+        InvoiceStatus::Paid
+    );
+
+    // Pay over the same channel with renepay and compare fees with pay
+    let (major, minor, patch) = MIN_RENEPAY_VERSION;
+    match cln_client.assert_min_cln_version(major, minor, patch).await {
+        Ok(()) => {
+            let lnd_bolt11 = lnd_client.create_invoice(Some(1_000_000), None).await?;
+            let balance_before = cln_client.balance().await?;
+            cln_client.pay_invoice(lnd_bolt11, None).await?;
+            let balance_after = cln_client.balance().await?;
+            let pay_fee_msat = fee_paid_msat(&balance_before, &balance_after, 1_000_000)?;
+
+            let lnd_bolt11 = lnd_client.create_invoice(Some(1_000_000), None).await?;
+            let balance_before = cln_client.balance().await?;
+            let cln_rene_preimage = cln_client.renepay(lnd_bolt11, None).await?;
+            let balance_after = cln_client.balance().await?;
+            let rene_fee_msat = fee_paid_msat(&balance_before, &balance_after, 1_000_000)?;
+
+            println!("cln renepay preimage: {}", cln_rene_preimage);
+            println!(
+                "cln pay fee msat: {}, renepay fee msat: {}",
+                pay_fee_msat, rene_fee_msat
+            );
+            // Direct channel to the payee, neither algorithm pays routing fees
+            assert_eq!(pay_fee_msat, rene_fee_msat);
+        }
+        Err(err) => println!("Skipping renepay: {}", err),
+    }
+
+    // Incoming channels to LND go through an accept all acceptor
+    let channel_acceptor = lnd_client
+        .start_channel_acceptor(ChannelAcceptorPolicy::default())
+        .await?;
+
+    assert_eq!(lnd_client.get_node_pubkey().await?, lnd_pubkey);
+    let lnd_connect_info = lnd_client.get_connect_info().await?;
+    let cln_channel_id = cln_client
+        .connect_and_open_channel(&lnd_connect_info, 1_500_000, None)
+        .await?;
+    println!("cln opened channel: {}", cln_channel_id);
     bitcoin_client_mining.generate_blocks(&mine_to_address, 10)?;
 
-    cln_client.wait_channels_active().await?;
+    cln_client.wait_channels_active(None).await?;
+    channel_acceptor.abort();
+
+    // LND pays its own hold invoices out to CLN and back so the HTLCs stay pending,
+    // CLN rejects the HTLC over its limit on the channel from LND
+    lnd_client.wait_channels_active(None).await?;
+    let mut in_channel = None;
+    let mut out_channel = None;
+    for channel in cln_client.list_peer_channels().await?.channels {
+        let scid = channel.short_channel_id.map(|scid| scid.to_string());
+        match channel.channel_id.map(|id| id.to_string()) == Some(cln_channel_id.clone()) {
+            true => in_channel = scid,
+            false => out_channel = scid,
+        }
+    }
+    let in_channel = in_channel.ok_or(anyhow!("CLN opened channel not found"))?;
+    let out_channel = out_channel.ok_or(anyhow!("LND opened channel not found"))?;
+
+    let mut pending_htlcs = Vec::new();
+    for _ in 0..=CLN_MAX_CONCURRENT_HTLCS {
+        let preimage = [
+            uuid::Uuid::new_v4().into_bytes(),
+            uuid::Uuid::new_v4().into_bytes(),
+        ]
+        .concat();
+        let payment_hash = sha256::Hash::hash(&preimage).to_byte_array();
+        let bolt11 = lnd_client
+            .create_hold_invoice(10_000, &payment_hash)
+            .await?;
+        pending_htlcs.push((payment_hash, bolt11));
+    }
+
+    let (rejected_hash, rejected_bolt11) = pending_htlcs.pop().expect("Invoices created");
+
+    let mut hold_payments = Vec::new();
+    for (payment_hash, bolt11) in &pending_htlcs {
+        let paying_client = LndClient::new(
+            "https://127.0.0.1:10009".to_string(),
+            lnd.tls_cert_path(),
+            lnd.admin_macaroon_path(),
+        )
+        .await?;
+        let (out_channel, in_channel, bolt11) =
+            (out_channel.clone(), in_channel.clone(), bolt11.clone());
+        hold_payments.push(tokio::spawn(async move {
+            paying_client
+                .pay_self_via_channels(&out_channel, &in_channel, &bolt11)
+                .await
+        }));
+
+        lnd_client
+            .wait_for_invoice_accepted(
+                &ln_regtest_rs::hex::encode(payment_hash),
+                Duration::from_secs(60),
+            )
+            .await?;
+    }
+
+    let htlc_state = cln_client.snapshot_htlc_state().await?;
+    assert!(htlc_state
+        .channels
+        .iter()
+        .any(|c| c.received_htlcs.len() == CLN_MAX_CONCURRENT_HTLCS as usize));
+
+    let err = lnd_client
+        .pay_self_via_channels(&out_channel, &in_channel, &rejected_bolt11)
+        .await
+        .expect_err("HTLC over the CLN limit was accepted");
+    println!("lnd htlc over cln limit failed: {}", err);
+    assert!(err.to_string().contains("TemporaryChannelFailure"));
+    assert_eq!(
+        lnd_client
+            .check_incoming_payment_status(&ln_regtest_rs::hex::encode(rejected_hash))
+            .await?,
+        InvoiceStatus::Unpaid
+    );
+
+    for (payment_hash, _) in &pending_htlcs {
+        lnd_client.cancel_hold_invoice(payment_hash).await?;
+    }
+    for hold_payment in hold_payments {
+        assert!(hold_payment.await?.is_err());
+    }
+
+    // CLN pays itself out over the channel it opened and back through LND,
+    // LND does not forward into a channel disabled for routing
+    let lnd_routing_chan_id = cln_scid_to_lnd_u64(&out_channel)?;
+    lnd_client
+        .disable_channel_for_routing(lnd_routing_chan_id)
+        .await?;
+    assert!(cln_client
+        .rebalance_channel(&in_channel, &out_channel, 100_000_000)
+        .await
+        .is_err());
+
+    lnd_client
+        .enable_channel_for_routing(lnd_routing_chan_id)
+        .await?;
+    cln_client
+        .rebalance_channel(&in_channel, &out_channel, 100_000_000)
+        .await?;
+
+    // Close channel and check funds are swept back on chain
+    let balance_before_close = cln_client.balance().await?;
+
+    let cln_channels = cln_client.list_peer_channels().await?;
+    let short_channel_id = cln_channels
+        .channels
+        .last()
+        .and_then(|c| c.short_channel_id.as_ref().map(|scid| scid.to_string()))
+        .ok_or(anyhow!("CLN has no channel to close"))?;
+
+    cln_client.close_channel(&short_channel_id, false).await?;
+    bitcoin_client_mining.generate_blocks(&mine_to_address, 10)?;
+    cln_client.wait_chain_sync(None).await?;
+
+    let balance_after_close = cln_client.balance().await?;
+    assert!(balance_after_close.on_chain_total > balance_before_close.on_chain_total);
+
+    // Send on chain funds from LND to CLN
+    let cln_onchain_address = cln_client.get_new_onchain_address(None).await?;
+    let txid = lnd_client
+        .send_on_chain(&cln_onchain_address, 100_000)
+        .await?;
+    println!("lnd on chain send txid: {}", txid);
+    bitcoin_client_mining.generate_blocks(&mine_to_address, 1)?;
+    cln_client.wait_chain_sync(None).await?;
+
+    // Private channels are seen as private by both sides
+    lnd_client
+        .open_channel(1_100_000, &cln_pubkey, None, false, None, None)
+        .await?;
+    bitcoin_client_mining.generate_blocks(&mine_to_address, 10)?;
+    lnd_client.wait_chain_sync(None).await?;
+    cln_client.wait_chain_sync(None).await?;
+    lnd_client.wait_channels_active(None).await?;
+    cln_client.wait_channels_active(None).await?;
+
+    let cln_channels = cln_client.list_peer_channels().await?;
+    let private_channel_id = cln_channels
+        .channels
+        .iter()
+        .find(|c| c.total_msat.map(|a| a.msat()) == Some(1_100_000_000))
+        .and_then(|c| c.short_channel_id.as_ref().map(|scid| scid.to_string()))
+        .ok_or(anyhow!("CLN has no private channel"))?;
+
+    assert!(
+        lnd_client
+            .get_channel_info(&private_channel_id)
+            .await?
+            .private
+    );
+    assert!(
+        cln_client
+            .get_channel_info(&private_channel_id)
+            .await?
+            .private
+    );
+
+    // Private channels are not gossiped to nodes outside the channel,
+    // a public channel opened alongside reaches Bob through the Hub
+    let hub_pubkey = network.hub().get_node_pubkey().await?;
+    let alice_pubkey = network.alice().get_node_pubkey().await?;
+    network
+        .alice()
+        .open_channel(1_100_000, &hub_pubkey, None, false, None, None)
+        .await?;
+    network
+        .hub()
+        .open_channel(1_200_000, &alice_pubkey, None, true, None, None)
+        .await?;
+    bitcoin_client_mining.generate_blocks(&mine_to_address, 10)?;
+    for node in [network.alice(), network.hub(), network.bob()] {
+        node.wait_chain_sync(None).await?;
+        node.wait_channels_active(None).await?;
+    }
+
+    let alice_channels = network.alice().list_peer_channels().await?;
+    let find_scid = |capacity_msat: u64| {
+        alice_channels
+            .channels
+            .iter()
+            .find(|c| c.total_msat.map(|a| a.msat()) == Some(capacity_msat))
+            .and_then(|c| c.short_channel_id.as_ref().map(|scid| scid.to_string()))
+    };
+    let private_scid = find_scid(1_100_000_000).ok_or(anyhow!("Alice has no private channel"))?;
+    let public_scid = find_scid(1_200_000_000).ok_or(anyhow!("Alice has no public channel"))?;
+
+    let mut bob_graph = network.bob().get_network_graph().await?;
+    for _ in 0..60 {
+        if bob_graph
+            .channels
+            .iter()
+            .any(|c| c.channel_id == public_scid)
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        bob_graph = network.bob().get_network_graph().await?;
+    }
+    assert!(bob_graph
+        .channels
+        .iter()
+        .any(|c| c.channel_id == public_scid));
+    assert!(!bob_graph
+        .channels
+        .iter()
+        .any(|c| c.channel_id == private_scid));
+
+    // Disconnect and reconnect LND from CLN
+    let cln_info = cln_client.get_connect_info().await?;
+
+    let lnd_peers = lnd_client.list_peers().await?;
+    assert!(lnd_peers.iter().any(|p| p.pubkey == cln_info.pubkey));
+
+    lnd_client.disconnect_peer(&cln_info.pubkey).await?;
+
+    let lnd_peers = lnd_client.list_peers().await?;
+    assert!(!lnd_peers.iter().any(|p| p.pubkey == cln_info.pubkey));
+
+    lnd_client
+        .connect_peer(
+            cln_info.pubkey.clone(),
+            cln_info.address.clone(),
+            cln_info.port,
+        )
+        .await?;
+
+    lnd_client
+        .reconnect_peer(
+            &cln_info.pubkey,
+            &cln_info.address,
+            cln_info.port,
+            Some(Duration::from_secs(1)),
+        )
+        .await?;
+    lnd_client.wait_channels_active(None).await?;
+
+    cln_client
+        .assert_no_pending_htlcs(Duration::from_secs(30))
+        .await?;
+
+    // One public channel was closed, the other and the private channel stay open
+    cln_client.wait_channels_active(None).await?;
+    let cln_health = cln_client.health_check().await?;
+    let lnd_health = lnd_client.health_check().await?;
+    println!("cln health: {:?}", cln_health);
+    println!("lnd health: {:?}", lnd_health);
+
+    for health in [&cln_health, &lnd_health] {
+        assert!(health.chain_synced);
+        assert_eq!(health.active_channels, 2);
+        assert_eq!(health.pending_channels, 0);
+    }
+
+    // Setting an alias restarts clnd, so a fresh client is needed to see it
+    clnd.set_alias("regtest-alias")?;
+    let cln_client = ClnClient::new(clnd.data_dir().clone(), Some(clnd.rpc_path())).await?;
+    cln_client.wait_chain_sync(None).await?;
+    assert_eq!(
+        cln_client.get_alias().await?,
+        Some("regtest-alias".to_string())
+    );
 
     Ok(())
 }
@@ -2,11 +2,59 @@
 
 use std::{path::PathBuf, str::FromStr};
 
-use anyhow::Result;
 use bitcoincore_rpc::{
-    bitcoin::{Address, Amount},
+    bitcoin::{Address, Amount, Transaction, Txid},
+    json::{AddressType, ScanTxOutRequest},
     Auth, Client, RpcApi,
 };
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{bail, ln_client::LightningClient, Result};
+
+/// Bitcoind wallet address type
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum BitcoinAddressType {
+    Legacy,
+    P2shSegwit,
+    Bech32,
+    Bech32m,
+}
+
+impl From<BitcoinAddressType> for AddressType {
+    fn from(address_type: BitcoinAddressType) -> Self {
+        match address_type {
+            BitcoinAddressType::Legacy => AddressType::Legacy,
+            BitcoinAddressType::P2shSegwit => AddressType::P2shSegwit,
+            BitcoinAddressType::Bech32 => AddressType::Bech32,
+            BitcoinAddressType::Bech32m => AddressType::Bech32m,
+        }
+    }
+}
+
+/// Utxo found by scanning the utxo set
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct ScannedUtxo {
+    pub txid: String,
+    pub vout: u32,
+    pub amount_sat: u64,
+    pub height: u64,
+}
+
+/// Multisig address
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
+pub struct MultisigAddress {
+    pub address: String,
+    #[serde(rename = "redeemScript")]
+    pub redeem_script: String,
+    pub descriptor: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportDescriptorResult {
+    success: bool,
+    error: Option<serde_json::Value>,
+}
 
 /// Bitcoin client
 pub struct BitcoinClient {
@@ -68,30 +116,102 @@ impl BitcoinClient {
         Ok(address.to_string())
     }
 
+    /// Get new address of address type
+    pub fn get_new_address_of_type(&self, address_type: BitcoinAddressType) -> Result<String> {
+        let client = &self.client;
+
+        let address = client
+            .get_new_address(Some(""), Some(address_type.into()))?
+            .assume_checked();
+
+        Ok(address.to_string())
+    }
+
     /// Generate blocks
     pub fn generate_blocks(&self, address: &str, block_count: u64) -> Result<()> {
         let client = &self.client;
 
         client.generate_to_address(
             block_count,
-            Address::from_str(address)?.assume_checked_ref(),
+            Address::from_str(address)
+                .map_err(anyhow::Error::from)?
+                .assume_checked_ref(),
         )?;
 
         Ok(())
     }
 
     /// Send to address
-    pub fn send_to_address(&self, address: &str, amount: u64) -> Result<()> {
+    ///
+    /// Returns the txid
+    pub fn send_to_address(&self, address: &str, amount: u64) -> Result<String> {
         let client = &self.client;
 
-        let address = Address::from_str(address)?.assume_checked();
+        let address = Address::from_str(address)
+            .map_err(anyhow::Error::from)?
+            .assume_checked();
         let amount = Amount::from_sat(amount);
 
-        client.send_to_address(&address, amount, None, None, None, None, None, None)?;
+        let txid = client.send_to_address(&address, amount, None, None, None, None, None, None)?;
+
+        Ok(txid.to_string())
+    }
+
+    /// Send to address and mine a block confirming it
+    ///
+    /// Returns the txid
+    pub fn fund_address(&self, address: &str, amount_btc: f64) -> Result<String> {
+        let client = &self.client;
+
+        let address = Address::from_str(address)
+            .map_err(anyhow::Error::from)?
+            .assume_checked();
+        let amount = Amount::from_btc(amount_btc).map_err(anyhow::Error::from)?;
+
+        let txid = client.send_to_address(&address, amount, None, None, None, None, None, None)?;
+
+        let mine_to_address = self.get_new_address()?;
+        self.generate_blocks(&mine_to_address, 1)?;
+
+        Ok(txid.to_string())
+    }
+
+    /// Fund on chain wallet of lightning node with a confirmed output
+    pub async fn fund_lightning_node(
+        &self,
+        client: &dyn LightningClient,
+        amount_btc: f64,
+    ) -> Result<()> {
+        let address = client.get_new_onchain_address(None).await?;
+
+        let txid = self.fund_address(&address, amount_btc)?;
+        tracing::debug!("Funded lightning node in {}", txid);
+
+        client.wait_chain_sync(None).await?;
 
         Ok(())
     }
 
+    /// Get block count
+    pub fn get_block_count(&self) -> Result<u64> {
+        let client = &self.client;
+
+        let block_count = client.get_block_count()?;
+
+        Ok(block_count)
+    }
+
+    /// Get raw transaction
+    pub fn get_raw_transaction(&self, txid: &str) -> Result<Transaction> {
+        let client = &self.client;
+
+        let txid = Txid::from_str(txid).map_err(anyhow::Error::from)?;
+
+        let transaction = client.get_raw_transaction(&txid, None)?;
+
+        Ok(transaction)
+    }
+
     pub fn get_balance(&self) -> Result<u64> {
         let client = &self.client;
 
@@ -111,4 +231,78 @@ impl BitcoinClient {
         println!("{:#?}", balance);
         Ok(())
     }
+
+    /// Scan utxo set for outputs paying to scripts
+    ///
+    /// Scripts are hex encoded scriptPubKeys
+    pub fn scan_utxos_by_script(&self, scripts: Vec<String>) -> Result<Vec<ScannedUtxo>> {
+        let client = &self.client;
+
+        let descriptors: Vec<ScanTxOutRequest> = scripts
+            .into_iter()
+            .map(|script| ScanTxOutRequest::Single(format!("raw({})", script)))
+            .collect();
+
+        let result = client.scan_tx_out_set_blocking(&descriptors)?;
+
+        let utxos = result
+            .unspents
+            .into_iter()
+            .map(|utxo| ScannedUtxo {
+                txid: utxo.txid.to_string(),
+                vout: utxo.vout,
+                amount_sat: utxo.amount.to_sat(),
+                height: utxo.height,
+            })
+            .collect();
+
+        Ok(utxos)
+    }
+
+    /// Abort in progress utxo set scan
+    pub fn cancel_scan(&self) -> Result<bool> {
+        let client = &self.client;
+
+        let aborted = client.call::<bool>("scantxoutset", &["abort".into()])?;
+
+        Ok(aborted)
+    }
+
+    /// Create p2wsh multisig address requiring `n_required` of the hex encoded keys
+    pub fn create_multisig_address(
+        &self,
+        keys: Vec<String>,
+        n_required: u32,
+    ) -> Result<MultisigAddress> {
+        let client = &self.client;
+
+        let multisig = client.call::<MultisigAddress>(
+            "createmultisig",
+            &[n_required.into(), keys.into(), "bech32".into()],
+        )?;
+
+        Ok(multisig)
+    }
+
+    /// Import multisig descriptor into wallet
+    pub fn add_multisig_to_wallet(&self, address: &MultisigAddress, label: &str) -> Result<()> {
+        let client = &self.client;
+
+        let request = json!([{
+            "desc": address.descriptor,
+            "timestamp": "now",
+            "label": label,
+        }]);
+
+        let results =
+            client.call::<Vec<ImportDescriptorResult>>("importdescriptors", &[request])?;
+
+        for result in results {
+            if !result.success {
+                bail!("Could not import multisig descriptor: {:?}", result.error);
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -1,48 +1,88 @@
 //! Bitcoind
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::anyhow;
+use bitcoincore_rpc::{bitcoin::Txid, Auth, Client, RpcApi};
+
+use crate::{bail, ln_client::LightningClient, RegtestError, Result};
 
 use std::{
+    fs::File,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
     path::PathBuf,
     process::{Child, Command, Stdio},
-    thread::sleep,
-    time::Duration,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
 /// Bitcoind
 pub struct Bitcoind {
     rpc_user: String,
     rpc_password: String,
-    _addr: PathBuf,
+    rpc_port: u16,
     data_dir: PathBuf,
     child: Option<Child>,
     zmq_raw_block: String,
     zmq_raw_tx: String,
+    log_file: Option<PathBuf>,
+    verify_zmq: bool,
 }
 
 impl Bitcoind {
     /// Create new [`Bitcoind`]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         data_dir: PathBuf,
-        addr: PathBuf,
         rpc_user: String,
         rpc_password: String,
+        rpc_port: u16,
         zmq_raw_block: String,
         zmq_raw_tx: String,
+        log_file: Option<PathBuf>,
+        verify_zmq: bool,
     ) -> Self {
         Bitcoind {
             rpc_user,
             rpc_password,
-            _addr: addr,
+            rpc_port,
             data_dir,
             child: None,
             zmq_raw_block,
             zmq_raw_tx,
+            log_file,
+            verify_zmq,
         }
     }
 
+    /// Create new [`Bitcoind`] with rpc and zmq on free local ports
+    pub fn with_random_ports(
+        data_dir: PathBuf,
+        rpc_user: String,
+        rpc_password: String,
+    ) -> Result<Self> {
+        // All listeners are held so the ports differ
+        let rpc_listener = TcpListener::bind("127.0.0.1:0")?;
+        let block_listener = TcpListener::bind("127.0.0.1:0")?;
+        let tx_listener = TcpListener::bind("127.0.0.1:0")?;
+
+        let rpc_port = rpc_listener.local_addr()?.port();
+        let zmq_raw_block = format!("tcp://127.0.0.1:{}", block_listener.local_addr()?.port());
+        let zmq_raw_tx = format!("tcp://127.0.0.1:{}", tx_listener.local_addr()?.port());
+
+        Ok(Self::new(
+            data_dir,
+            rpc_user,
+            rpc_password,
+            rpc_port,
+            zmq_raw_block,
+            zmq_raw_tx,
+            None,
+            false,
+        ))
+    }
+
     /// Start bitcoind
-    pub fn start_bitcoind(&mut self) -> Result<()> {
+    pub async fn start_bitcoind(&mut self) -> Result<()> {
         println!("Starting btcd");
 
         std::fs::create_dir_all(&self.data_dir).unwrap();
@@ -56,26 +96,76 @@ impl Bitcoind {
         cmd.arg("-rpcallowip=0.0.0.0/0");
         cmd.arg(format!("-rpcuser={}", self.rpc_user));
         cmd.arg(format!("-rpcpassword={}", self.rpc_password));
+        cmd.arg(format!("-rpcport={}", self.rpc_port));
         cmd.arg(format!("-zmqpubrawblock={}", self.zmq_raw_block));
         cmd.arg(format!("-zmqpubrawtx={}", self.zmq_raw_tx));
 
-        //        cmd.arg(format!("-bind={}", self.addr.to_string_lossy()));
-
-        // Send output to dev null
-        cmd.stdout(Stdio::null());
+        match &self.log_file {
+            Some(log_file) => {
+                let log = File::create(log_file)?;
+                cmd.stdout(log.try_clone()?);
+                cmd.stderr(log);
+            }
+            None => {
+                // Send output to dev null
+                cmd.stdout(Stdio::null());
+            }
+        }
 
-        let child = cmd.spawn().unwrap();
+        let child = cmd.spawn().map_err(RegtestError::ProcessSpawnError)?;
 
         self.child = Some(child);
 
         // Let bitcoind start up
-        sleep(Duration::from_secs(5));
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        if self.verify_zmq {
+            self.verify_zmq_block_notifications(Duration::from_secs(10))
+                .await?;
+            self.verify_zmq_tx_notifications(Duration::from_secs(10))
+                .await?;
+        }
 
         Ok(())
     }
 
+    /// Data dir
+    pub fn data_dir(&self) -> &PathBuf {
+        &self.data_dir
+    }
+
+    /// Rpc user
+    pub fn rpc_user(&self) -> &str {
+        &self.rpc_user
+    }
+
+    /// Rpc password
+    pub fn rpc_password(&self) -> &str {
+        &self.rpc_password
+    }
+
+    /// Rpc port
+    pub fn rpc_port(&self) -> u16 {
+        self.rpc_port
+    }
+
+    /// Rpc url
+    pub fn rpc_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.rpc_port)
+    }
+
+    /// Zmq raw block address
+    pub fn zmq_block_addr(&self) -> &str {
+        &self.zmq_raw_block
+    }
+
+    /// Zmq raw tx address
+    pub fn zmq_tx_addr(&self) -> &str {
+        &self.zmq_raw_tx
+    }
+
     pub fn pid(&self) -> Result<u32> {
-        let child = self.child.as_ref().ok_or(anyhow!("Unknow child"))?;
+        let child = self.child.as_ref().ok_or(RegtestError::NotFound)?;
 
         Ok(child.id())
     }
@@ -93,13 +183,338 @@ impl Bitcoind {
 
         Ok(())
     }
+
+    /// Generate blocks to address with `bitcoin-cli`
+    ///
+    /// Returns the block hashes
+    pub fn generate_blocks(&self, count: u32, address: &str) -> Result<Vec<String>> {
+        let output = Command::new("bitcoin-cli")
+            .arg("-regtest")
+            .arg(format!("-datadir={}", self.data_dir.to_string_lossy()))
+            .arg(format!("-rpcuser={}", self.rpc_user))
+            .arg(format!("-rpcpassword={}", self.rpc_password))
+            .arg(format!("-rpcport={}", self.rpc_port))
+            .arg("generatetoaddress")
+            .arg(count.to_string())
+            .arg(address)
+            .output()?;
+
+        if !output.status.success() {
+            bail!(
+                "bitcoin-cli generatetoaddress failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let block_hashes: Vec<String> = serde_json::from_slice(&output.stdout)?;
+
+        Ok(block_hashes)
+    }
+
+    /// Generate blocks and wait for nodes to sync to chain
+    pub async fn mine_and_sync(
+        &self,
+        count: u32,
+        address: &str,
+        nodes: &[&dyn LightningClient],
+    ) -> Result<Vec<String>> {
+        let block_hashes = self.generate_blocks(count, address)?;
+
+        for node in nodes {
+            node.wait_chain_sync(None).await?;
+        }
+
+        Ok(block_hashes)
+    }
+
+    /// Wait for output to exist unspent with at least `min_confs` confirmations
+    pub async fn wait_for_output_confirmations(
+        &self,
+        txid: &str,
+        vout: u32,
+        min_confs: u32,
+        timeout: Duration,
+    ) -> Result<()> {
+        let client = self.rpc_client()?;
+        let txid = Txid::from_str(txid).map_err(anyhow::Error::from)?;
+
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            let confirmations = client
+                .get_tx_out(&txid, vout, Some(true))?
+                .map(|tx_out| tx_out.confirmations);
+
+            if confirmations.is_some_and(|confs| confs >= min_confs) {
+                return Ok(());
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    /// Verify a `rawblock` zmq notification is received after mining a block
+    pub async fn verify_zmq_block_notifications(&self, timeout: Duration) -> Result<()> {
+        self.verify_zmq_notification(&self.zmq_raw_block, "rawblock", timeout)
+            .await
+    }
+
+    /// Verify a `rawtx` zmq notification is received after mining a block
+    pub async fn verify_zmq_tx_notifications(&self, timeout: Duration) -> Result<()> {
+        self.verify_zmq_notification(&self.zmq_raw_tx, "rawtx", timeout)
+            .await
+    }
+
+    async fn verify_zmq_notification(
+        &self,
+        endpoint: &str,
+        topic: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut stream = zmq_subscribe(endpoint, topic, timeout)?;
+
+        // Give bitcoind time to register the subscription
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        self.mine_block()?;
+
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            let (flags, body) = zmq_read_frame(&mut stream)?;
+
+            // Skip commands and the remaining frames of other messages
+            if flags & ZMQ_FLAG_COMMAND == 0 && body == topic.as_bytes() {
+                tracing::info!("Received bitcoind zmq {} notification", topic);
+                return Ok(());
+            }
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    /// Submit raw block
+    ///
+    /// Returns the rejection reason if bitcoind did not accept the block
+    pub fn submit_block(&self, block_hex: &str) -> Result<Option<String>> {
+        let client = self.rpc_client()?;
+
+        let rejection = client.call::<Option<String>>("submitblock", &[block_hex.into()])?;
+
+        Ok(rejection)
+    }
+
+    /// Mine a block to an anyone can spend output
+    fn mine_block(&self) -> Result<()> {
+        let client = self.rpc_client()?;
+
+        client.call::<serde_json::Value>("generatetodescriptor", &[1.into(), "raw(51)".into()])?;
+
+        Ok(())
+    }
+
+    fn rpc_client(&self) -> Result<Client> {
+        let client = Client::new(
+            &self.rpc_url(),
+            Auth::UserPass(self.rpc_user.clone(), self.rpc_password.clone()),
+        )?;
+
+        Ok(client)
+    }
+
+    /// Print the last lines of the log file to stderr
+    fn print_log_tail(&self, line_count: usize) {
+        let Some(log_file) = &self.log_file else {
+            return;
+        };
+
+        match std::fs::read_to_string(log_file) {
+            Ok(log) => {
+                let lines: Vec<&str> = log.lines().collect();
+                let start = lines.len().saturating_sub(line_count);
+
+                eprintln!("Last bitcoind log lines ({}):", log_file.display());
+                for line in &lines[start..] {
+                    eprintln!("{}", line);
+                }
+            }
+            Err(err) => eprintln!("Could not read bitcoind log: {}", err),
+        }
+    }
 }
 
 impl Drop for Bitcoind {
     fn drop(&mut self) {
         tracing::info!("Dropping bitcoind");
+        if std::thread::panicking() {
+            self.print_log_tail(100);
+        }
         if let Err(err) = self.stop_bitcoind() {
             tracing::error!("Could not stop bitcoind: {}", err);
         }
     }
 }
+
+const ZMQ_FLAG_LONG: u8 = 0x02;
+const ZMQ_FLAG_COMMAND: u8 = 0x04;
+/// Largest frame accepted from bitcoind, well above the max block size
+const ZMQ_MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// Open a ZMTP 3.0 SUB connection and subscribe to topic
+fn zmq_subscribe(endpoint: &str, topic: &str, timeout: Duration) -> Result<TcpStream> {
+    let addr = endpoint.trim_start_matches("tcp://");
+
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    stream.write_all(&zmq_greeting())?;
+
+    let mut peer_greeting = [0u8; 64];
+    stream.read_exact(&mut peer_greeting)?;
+
+    if !zmq_valid_greeting(&peer_greeting) {
+        bail!("Invalid zmq greeting from {}", endpoint);
+    }
+
+    // READY command announcing a SUB socket
+    let mut ready = vec![5];
+    ready.extend_from_slice(b"READY");
+    ready.push(11);
+    ready.extend_from_slice(b"Socket-Type");
+    ready.extend_from_slice(&3u32.to_be_bytes());
+    ready.extend_from_slice(b"SUB");
+    zmq_write_frame(&mut stream, ZMQ_FLAG_COMMAND, &ready)?;
+
+    let (flags, _) = zmq_read_frame(&mut stream)?;
+    if flags & ZMQ_FLAG_COMMAND == 0 {
+        bail!("Expected zmq READY from {}", endpoint);
+    }
+
+    // ZMTP 3.0 subscriptions are sent as a message prefixed with 0x01
+    let mut subscribe = vec![1];
+    subscribe.extend_from_slice(topic.as_bytes());
+    zmq_write_frame(&mut stream, 0, &subscribe)?;
+
+    Ok(stream)
+}
+
+/// Greeting: signature, version 3.0, NULL mechanism, as-server false
+fn zmq_greeting() -> [u8; 64] {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+fn zmq_valid_greeting(greeting: &[u8; 64]) -> bool {
+    greeting[0] == 0xff && greeting[9] == 0x7f
+}
+
+fn zmq_write_frame<W: Write>(stream: &mut W, flags: u8, body: &[u8]) -> Result<()> {
+    let len = u8::try_from(body.len()).map_err(|_| anyhow!("zmq frame too long"))?;
+
+    stream.write_all(&[flags, len])?;
+    stream.write_all(body)?;
+
+    Ok(())
+}
+
+fn zmq_read_frame<R: Read>(stream: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags)?;
+
+    let len = match flags[0] & ZMQ_FLAG_LONG {
+        0 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => {
+            let mut len = [0u8; 8];
+            stream.read_exact(&mut len)?;
+            usize::try_from(u64::from_be_bytes(len)).unwrap_or(usize::MAX)
+        }
+    };
+
+    if len > ZMQ_MAX_FRAME_LEN {
+        bail!("zmq frame of {} bytes exceeds limit", len);
+    }
+
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+
+    Ok((flags[0], body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_greeting() {
+        let greeting = zmq_greeting();
+        assert!(zmq_valid_greeting(&greeting));
+        assert_eq!(greeting[10], 3);
+        assert_eq!(&greeting[12..16], b"NULL");
+
+        let mut invalid = greeting;
+        invalid[0] = 0;
+        assert!(!zmq_valid_greeting(&invalid));
+    }
+
+    #[test]
+    fn test_short_frame() {
+        let mut frame = Vec::new();
+        zmq_write_frame(&mut frame, ZMQ_FLAG_COMMAND, b"READY").unwrap();
+        assert_eq!(frame, [&[ZMQ_FLAG_COMMAND, 5][..], &b"READY"[..]].concat());
+
+        let (flags, body) = zmq_read_frame(&mut frame.as_slice()).unwrap();
+        assert_eq!(flags, ZMQ_FLAG_COMMAND);
+        assert_eq!(body, b"READY");
+    }
+
+    #[test]
+    fn test_long_frame() {
+        let body = vec![7u8; 300];
+        let mut frame = vec![ZMQ_FLAG_LONG];
+        frame.extend_from_slice(&300u64.to_be_bytes());
+        frame.extend_from_slice(&body);
+
+        let (flags, read_body) = zmq_read_frame(&mut frame.as_slice()).unwrap();
+        assert_eq!(flags, ZMQ_FLAG_LONG);
+        assert_eq!(read_body, body);
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        // Body shorter than the length
+        let frame = [0, 5, b'r', b'a', b'w'];
+        assert!(zmq_read_frame(&mut frame.as_slice()).is_err());
+
+        // Long length cut off
+        let frame = [ZMQ_FLAG_LONG, 0, 0, 1];
+        assert!(zmq_read_frame(&mut frame.as_slice()).is_err());
+
+        // No length
+        let frame = [0];
+        assert!(zmq_read_frame(&mut frame.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut frame = vec![ZMQ_FLAG_LONG];
+        frame.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(zmq_read_frame(&mut frame.as_slice()).is_err());
+
+        let mut frame = vec![ZMQ_FLAG_LONG];
+        frame.extend_from_slice(&(ZMQ_MAX_FRAME_LEN as u64 + 1).to_be_bytes());
+        assert!(zmq_read_frame(&mut frame.as_slice()).is_err());
+
+        // Short frames can not be written with a body over 255 bytes
+        assert!(zmq_write_frame(&mut Vec::<u8>::new(), 0, &[0u8; 256]).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! Regtest environment builder
+
+use std::{net::SocketAddr, path::PathBuf, str::FromStr};
+
+use anyhow::anyhow;
+use tempfile::{tempdir, TempDir};
+
+use crate::{
+    bitcoin_client::BitcoinClient,
+    bitcoind::Bitcoind,
+    cln::Clnd,
+    ln_client::{ClnClient, LightningClient, LndClient},
+    lnd::Lnd,
+    RegtestError, Result,
+};
+
+/// Lightning node implementation
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum NodeType {
+    Cln,
+    Lnd,
+}
+
+#[derive(Debug, Clone)]
+struct NodeConfig {
+    name: String,
+    node_type: NodeType,
+    addr: String,
+    rpc_listen: Option<String>,
+}
+
+/// Builder for [`RegtestEnv`]
+#[derive(Debug, Clone)]
+pub struct RegtestNodeBuilder {
+    data_dir: Option<PathBuf>,
+    bitcoind_addr: String,
+    rpc_user: String,
+    rpc_password: String,
+    zmq_raw_block: String,
+    zmq_raw_tx: String,
+    nodes: Vec<NodeConfig>,
+}
+
+impl Default for RegtestNodeBuilder {
+    fn default() -> Self {
+        Self {
+            data_dir: None,
+            bitcoind_addr: "127.0.0.1:18443".to_string(),
+            rpc_user: "testuser".to_string(),
+            rpc_password: "testpassword".to_string(),
+            zmq_raw_block: "tcp://127.0.0.1:28332".to_string(),
+            zmq_raw_tx: "tcp://127.0.0.1:28333".to_string(),
+            nodes: vec![],
+        }
+    }
+}
+
+impl RegtestNodeBuilder {
+    /// Create new [`RegtestNodeBuilder`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Data dir for all nodes, a temp dir is used if not set
+    pub fn data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.data_dir = Some(data_dir);
+        self
+    }
+
+    /// Bitcoind rpc address
+    pub fn bitcoind_addr(mut self, addr: &str) -> Self {
+        self.bitcoind_addr = addr.to_string();
+        self
+    }
+
+    /// Bitcoind rpc credentials
+    pub fn rpc_credentials(mut self, rpc_user: &str, rpc_password: &str) -> Self {
+        self.rpc_user = rpc_user.to_string();
+        self.rpc_password = rpc_password.to_string();
+        self
+    }
+
+    /// Bitcoind zmq endpoints
+    pub fn zmq(mut self, zmq_raw_block: &str, zmq_raw_tx: &str) -> Self {
+        self.zmq_raw_block = zmq_raw_block.to_string();
+        self.zmq_raw_tx = zmq_raw_tx.to_string();
+        self
+    }
+
+    /// Add CLN node listening on addr
+    pub fn add_cln(mut self, name: &str, addr: &str) -> Self {
+        self.nodes.push(NodeConfig {
+            name: name.to_string(),
+            node_type: NodeType::Cln,
+            addr: addr.to_string(),
+            rpc_listen: None,
+        });
+        self
+    }
+
+    /// Add LND node listening on addr with grpc on rpc_listen
+    pub fn add_lnd(mut self, name: &str, addr: &str, rpc_listen: &str) -> Self {
+        self.nodes.push(NodeConfig {
+            name: name.to_string(),
+            node_type: NodeType::Lnd,
+            addr: addr.to_string(),
+            rpc_listen: Some(rpc_listen.to_string()),
+        });
+        self
+    }
+
+    /// Start bitcoind and all nodes
+    pub async fn build(self) -> Result<RegtestEnv> {
+        let (data_dir, temp_dir) = match &self.data_dir {
+            Some(data_dir) => (data_dir.clone(), None),
+            None => {
+                let temp_dir = tempdir()?;
+                (temp_dir.path().to_path_buf(), Some(temp_dir))
+            }
+        };
+
+        let btc_dir = data_dir.join("bitcoind");
+        std::fs::create_dir_all(&btc_dir)?;
+
+        let rpc_port = SocketAddr::from_str(&self.bitcoind_addr)
+            .map_err(anyhow::Error::from)?
+            .port();
+
+        let mut bitcoind = Bitcoind::new(
+            btc_dir.clone(),
+            self.rpc_user.clone(),
+            self.rpc_password.clone(),
+            rpc_port,
+            self.zmq_raw_block.clone(),
+            self.zmq_raw_tx.clone(),
+            None,
+            false,
+        );
+        bitcoind.start_bitcoind().await?;
+
+        let bitcoin_client = BitcoinClient::new(
+            "regtest_env".to_string(),
+            bitcoind.rpc_url().into(),
+            None,
+            Some(self.rpc_user.clone()),
+            Some(self.rpc_password.clone()),
+        )?;
+        bitcoin_client.create_wallet().ok();
+        bitcoin_client.load_wallet()?;
+
+        let mine_to_address = bitcoin_client.get_new_address()?;
+        bitcoin_client.generate_blocks(&mine_to_address, 200)?;
+
+        let mut cln_nodes = Vec::new();
+        let mut lnd_nodes = Vec::new();
+
+        for node in self.nodes {
+            tracing::info!("Starting {:?} node {}", node.node_type, node.name);
+
+            let node_dir = data_dir.join(&node.name);
+            std::fs::create_dir_all(&node_dir)?;
+
+            match node.node_type {
+                NodeType::Cln => {
+                    let mut clnd = Clnd::new(
+                        btc_dir.clone(),
+                        node_dir.clone(),
+                        PathBuf::from_str(&node.addr)?,
+                        self.rpc_user.clone(),
+                        self.rpc_password.clone(),
+                        rpc_port,
+                        None,
+                        vec![],
+                        None,
+                    );
+                    clnd.start_clnd()?;
+
+                    let client = ClnClient::new(node_dir, None).await?;
+                    client.wait_chain_sync(None).await?;
+
+                    cln_nodes.push(ClnNode {
+                        name: node.name,
+                        client,
+                        _clnd: clnd,
+                    });
+                }
+                NodeType::Lnd => {
+                    let rpc_listen = node
+                        .rpc_listen
+                        .ok_or(anyhow!("LND node requires rpc listen address"))?;
+
+                    let mut lnd = Lnd::new(
+                        btc_dir.clone(),
+                        node_dir.clone(),
+                        PathBuf::from_str(&node.addr)?,
+                        rpc_listen.clone(),
+                        self.rpc_user.clone(),
+                        self.rpc_password.clone(),
+                        rpc_port,
+                        self.zmq_raw_block.clone(),
+                        self.zmq_raw_tx.clone(),
+                        None,
+                        None,
+                        false,
+                    );
+                    lnd.start_lnd().await?;
+
+                    let client = LndClient::new(
+                        format!("https://{}", rpc_listen),
+                        lnd.tls_cert_path(),
+                        lnd.admin_macaroon_path(),
+                    )
+                    .await?;
+                    client.wait_chain_sync(None).await?;
+
+                    lnd_nodes.push(LndNode {
+                        name: node.name,
+                        client,
+                        _lnd: lnd,
+                    });
+                }
+            }
+        }
+
+        Ok(RegtestEnv {
+            cln_nodes,
+            lnd_nodes,
+            bitcoin_client,
+            mine_to_address,
+            _bitcoind: bitcoind,
+            _temp_dir: temp_dir,
+        })
+    }
+}
+
+struct ClnNode {
+    name: String,
+    client: ClnClient,
+    _clnd: Clnd,
+}
+
+struct LndNode {
+    name: String,
+    client: LndClient,
+    _lnd: Lnd,
+}
+
+/// Running regtest environment
+///
+/// Lightning nodes are stopped before bitcoind when dropped
+pub struct RegtestEnv {
+    cln_nodes: Vec<ClnNode>,
+    lnd_nodes: Vec<LndNode>,
+    bitcoin_client: BitcoinClient,
+    mine_to_address: String,
+    _bitcoind: Bitcoind,
+    _temp_dir: Option<TempDir>,
+}
+
+impl RegtestEnv {
+    /// Bitcoin client with a funded wallet
+    pub fn bitcoin_client(&self) -> &BitcoinClient {
+        &self.bitcoin_client
+    }
+
+    /// Get CLN client by name
+    pub fn cln(&self, name: &str) -> Option<&ClnClient> {
+        self.cln_nodes
+            .iter()
+            .find(|n| n.name == name)
+            .map(|n| &n.client)
+    }
+
+    /// Get LND client by name
+    pub fn lnd(&self, name: &str) -> Option<&LndClient> {
+        self.lnd_nodes
+            .iter()
+            .find(|n| n.name == name)
+            .map(|n| &n.client)
+    }
+
+    /// Get node by name
+    pub fn node(&self, name: &str) -> Option<&dyn LightningClient> {
+        match self.cln(name) {
+            Some(cln) => Some(cln),
+            None => self.lnd(name).map(|lnd| lnd as &dyn LightningClient),
+        }
+    }
+
+    fn nodes(&self) -> Vec<&dyn LightningClient> {
+        let cln_nodes = self
+            .cln_nodes
+            .iter()
+            .map(|n| &n.client as &dyn LightningClient);
+        let lnd_nodes = self
+            .lnd_nodes
+            .iter()
+            .map(|n| &n.client as &dyn LightningClient);
+
+        cln_nodes.chain(lnd_nodes).collect()
+    }
+
+    /// Mine blocks and wait for all nodes to sync
+    pub async fn mine_blocks(&self, block_count: u64) -> Result<()> {
+        self.bitcoin_client
+            .generate_blocks(&self.mine_to_address, block_count)?;
+
+        for node in self.nodes() {
+            node.wait_chain_sync(None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Fund `from` on chain and open channel to `to`
+    pub async fn fund_and_open_channel(
+        &self,
+        from: &str,
+        to: &str,
+        amount_sat: u64,
+        push_amount: Option<u64>,
+    ) -> Result<()> {
+        let from_node = self.node(from).ok_or(RegtestError::NotFound)?;
+        let to_node = self.node(to).ok_or(RegtestError::NotFound)?;
+
+        // Extra funds cover the funding tx fee
+        let address = from_node.get_new_onchain_address(None).await?;
+        self.bitcoin_client
+            .send_to_address(&address, amount_sat + 100_000)?;
+        self.mine_blocks(10).await?;
+
+        let to_info = to_node.get_connect_info().await?;
+        from_node
+            .connect_peer(to_info.pubkey.clone(), to_info.address, to_info.port)
+            .await?;
+        from_node
+            .open_channel(amount_sat, &to_info.pubkey, push_amount, true, None, None)
+            .await?;
+
+        self.mine_blocks(6).await?;
+
+        from_node.wait_channels_active(None).await?;
+        to_node.wait_channels_active(None).await?;
+
+        Ok(())
+    }
+}
+
+impl Drop for RegtestEnv {
+    fn drop(&mut self) {
+        tracing::info!("Dropping regtest env");
+        // Stop lightning nodes while bitcoind is still running
+        self.lnd_nodes.clear();
+        self.cln_nodes.clear();
+    }
+}
@@ -7,7 +7,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::{anyhow, bail, Result};
+use crate::{bail, RegtestError, Result};
 
 /// Clnd
 pub struct Clnd {
@@ -17,16 +17,27 @@ pub struct Clnd {
     child: Option<Child>,
     bitcoin_rpc_user: String,
     bitcoin_rpc_password: String,
+    bitcoin_rpc_port: u16,
+    max_concurrent_htlcs: Option<u32>,
+    plugins: Vec<PathBuf>,
+    log_level: Option<String>,
+    grpc_port: Option<u16>,
+    alias: Option<String>,
 }
 
 impl Clnd {
     /// Create new [`Clnd`]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         bitcoin_data_dir: PathBuf,
         data_dir: PathBuf,
         addr: PathBuf,
         bitcoin_rpc_user: String,
         bitcoin_rpc_password: String,
+        bitcoin_rpc_port: u16,
+        max_concurrent_htlcs: Option<u32>,
+        plugins: Vec<PathBuf>,
+        log_level: Option<String>,
     ) -> Self {
         Self {
             data_dir,
@@ -35,6 +46,12 @@ impl Clnd {
             child: None,
             bitcoin_rpc_user,
             bitcoin_rpc_password,
+            bitcoin_rpc_port,
+            max_concurrent_htlcs,
+            plugins,
+            log_level,
+            grpc_port: None,
+            alias: None,
         }
     }
 
@@ -56,13 +73,35 @@ impl Clnd {
             "--bitcoin-rpcpassword={}",
             self.bitcoin_rpc_password
         ));
+        cmd.arg("--bitcoin-rpcconnect=127.0.0.1");
+        cmd.arg(format!("--bitcoin-rpcport={}", self.bitcoin_rpc_port));
 
         cmd.arg(format!("--bind-addr={}", self.addr.to_string_lossy()));
 
+        if let Some(max_concurrent_htlcs) = self.max_concurrent_htlcs {
+            cmd.arg(format!("--max-concurrent-htlcs={}", max_concurrent_htlcs));
+        }
+
+        for plugin in &self.plugins {
+            cmd.arg(format!("--plugin={}", plugin.display()));
+        }
+
+        if let Some(log_level) = &self.log_level {
+            cmd.arg(format!("--log-level={}", log_level));
+        }
+
+        if let Some(grpc_port) = self.grpc_port {
+            cmd.arg(format!("--grpc-port={}", grpc_port));
+        }
+
+        if let Some(alias) = &self.alias {
+            cmd.arg(format!("--alias={}", alias));
+        }
+
         // Send output to dev null
         cmd.stdout(Stdio::null());
 
-        let child = cmd.spawn()?;
+        let child = cmd.spawn().map_err(RegtestError::ProcessSpawnError)?;
 
         self.child = Some(child);
 
@@ -72,8 +111,37 @@ impl Clnd {
         Ok(())
     }
 
+    /// Set port of the cln-grpc plugin, takes effect on next start
+    pub fn set_grpc_port(&mut self, port: u16) {
+        self.grpc_port = Some(port);
+    }
+
+    /// Set node alias, restarting clnd if it is running
+    ///
+    /// Clients connected to the old process have to be recreated
+    pub fn set_alias(&mut self, alias: &str) -> Result<()> {
+        self.alias = Some(alias.to_string());
+
+        if self.child.is_some() {
+            self.stop_clnd()?;
+            self.start_clnd()?;
+        }
+
+        Ok(())
+    }
+
+    /// Data dir
+    pub fn data_dir(&self) -> &PathBuf {
+        &self.data_dir
+    }
+
+    /// Path to clnd rpc socket
+    pub fn rpc_path(&self) -> PathBuf {
+        self.data_dir.join("regtest/lightning-rpc")
+    }
+
     pub fn pid(&self) -> Result<u32> {
-        let child = self.child.as_ref().ok_or(anyhow!("Unknow child"))?;
+        let child = self.child.as_ref().ok_or(RegtestError::NotFound)?;
 
         Ok(child.id())
     }
@@ -85,6 +153,8 @@ impl Clnd {
         match child {
             Some(mut child) => {
                 child.kill()?;
+                // Reap the process so a restart does not race the old one
+                child.wait()?;
             }
             None => bail!("No child to kill"),
         }
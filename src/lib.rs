@@ -1,16 +1,136 @@
 pub mod bitcoin_client;
 pub mod bitcoind;
+pub mod builder;
 pub mod cln;
 pub mod hex;
 pub mod lnd;
+pub mod network;
 
 pub mod ln_client;
 
+pub use bitcoin_client::BitcoinClient;
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum InvoiceStatus {
     Paid,
     Pending,
+    Accepted,
     Unpaid,
     Expired,
     Failed,
 }
+
+/// Result type returned by the public api
+pub type Result<T, E = RegtestError> = std::result::Result<T, E>;
+
+/// Return early with a [`RegtestError::Other`] built from a format string
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::RegtestError::from(anyhow::anyhow!($($arg)*)))
+    };
+}
+
+pub(crate) use bail;
+
+#[derive(Debug)]
+pub enum RegtestError {
+    /// Lightning or bitcoin rpc call failed
+    RpcError(String),
+    /// Timed out waiting for a node
+    Timeout,
+    /// Requested item (channel, invoice, node, ..) does not exist
+    NotFound,
+    /// Item (peer, wallet, ..) already exists
+    AlreadyExists,
+    /// Could not spawn or stop a node process
+    ProcessSpawnError(std::io::Error),
+    /// Io error
+    Io(std::io::Error),
+    AssertionFailed {
+        expected: u64,
+        actual: u64,
+    },
+    /// Any other error
+    Other(anyhow::Error),
+}
+
+impl std::error::Error for RegtestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ProcessSpawnError(err) | Self::Io(err) => Some(err),
+            Self::Other(err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RegtestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RpcError(err) => write!(f, "Rpc error: {}", err),
+            Self::Timeout => write!(f, "Timeout"),
+            Self::NotFound => write!(f, "Not found"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::ProcessSpawnError(err) => write!(f, "Could not spawn process: {}", err),
+            Self::Io(err) => write!(f, "Io error: {}", err),
+            Self::AssertionFailed { expected, actual } => {
+                write!(
+                    f,
+                    "Assertion failed: expected at least {}, got {}",
+                    expected, actual
+                )
+            }
+            Self::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RegtestError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Other(err)
+    }
+}
+
+impl From<std::io::Error> for RegtestError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<cln_rpc::RpcError> for RegtestError {
+    fn from(err: cln_rpc::RpcError) -> Self {
+        Self::RpcError(err.to_string())
+    }
+}
+
+impl From<fedimint_tonic_lnd::tonic::Status> for RegtestError {
+    fn from(err: fedimint_tonic_lnd::tonic::Status) -> Self {
+        Self::RpcError(err.message().to_string())
+    }
+}
+
+impl From<bitcoincore_rpc::Error> for RegtestError {
+    fn from(err: bitcoincore_rpc::Error) -> Self {
+        Self::RpcError(err.to_string())
+    }
+}
+
+impl From<std::convert::Infallible> for RegtestError {
+    fn from(err: std::convert::Infallible) -> Self {
+        match err {}
+    }
+}
+
+macro_rules! impl_from_via_anyhow {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for RegtestError {
+                fn from(err: $err) -> Self {
+                    Self::Other(err.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_via_anyhow!(hex::Error, serde_json::Error, std::num::ParseIntError);
@@ -0,0 +1,103 @@
+//! CLN channel cache
+
+use std::{collections::HashMap, time::Duration};
+
+use cln_rpc::model::responses::ListchannelsResponse;
+use tokio::{sync::Mutex, time::Instant};
+
+use super::{
+    types::{ChannelType, ListChannelsFilter},
+    ClnClient, LightningClient,
+};
+use crate::Result;
+
+/// Cache of [`ClnClient`] `list_channels` responses
+pub struct ClnChannelCache {
+    client: ClnClient,
+    ttl: Duration,
+    channels: Mutex<HashMap<ListChannelsFilter, (Instant, ListchannelsResponse)>>,
+}
+
+impl ClnChannelCache {
+    /// Create new [`ClnChannelCache`] with a ttl of 1 second
+    pub fn new(client: ClnClient) -> Self {
+        Self::with_ttl(client, Duration::from_secs(1))
+    }
+
+    /// Create new [`ClnChannelCache`] with ttl
+    pub fn with_ttl(client: ClnClient, ttl: Duration) -> Self {
+        Self {
+            client,
+            ttl,
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Underlying [`ClnClient`]
+    pub fn client(&self) -> &ClnClient {
+        &self.client
+    }
+
+    /// List channels, served from cache while within ttl
+    pub async fn list_channels(&self, filter: ListChannelsFilter) -> Result<ListchannelsResponse> {
+        let mut channels = self.channels.lock().await;
+
+        if let Some((fetched_at, response)) = channels.get(&filter) {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(response.clone());
+            }
+        }
+
+        let response = self.client.list_channels(filter.clone()).await?;
+
+        channels.insert(filter, (Instant::now(), response.clone()));
+
+        Ok(response)
+    }
+
+    /// Clear cached channels
+    pub async fn invalidate(&self) {
+        self.channels.lock().await.clear();
+    }
+
+    /// Update channel policy and invalidate cache
+    pub async fn update_channel_policy(
+        &self,
+        short_channel_id: &str,
+        base_fee_msat: u64,
+        fee_ppm: u64,
+        cltv_delta: u32,
+    ) -> Result<()> {
+        let res = self
+            .client
+            .update_channel_policy(short_channel_id, base_fee_msat, fee_ppm, cltv_delta)
+            .await;
+        self.invalidate().await;
+        res
+    }
+
+    /// Open channel and invalidate cache
+    pub async fn open_channel(
+        &self,
+        amount_sat: u64,
+        peer_id: &str,
+        push_amount: Option<u64>,
+        announce: bool,
+        channel_type: Option<ChannelType>,
+        minconf: Option<u32>,
+    ) -> Result<()> {
+        let res = self
+            .client
+            .open_channel(
+                amount_sat,
+                peer_id,
+                push_amount,
+                announce,
+                channel_type,
+                minconf,
+            )
+            .await;
+        self.invalidate().await;
+        res
+    }
+}
@@ -1,37 +1,150 @@
 //! CLN rpc client
 
-use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::anyhow;
 use async_trait::async_trait;
 use cln_rpc::{
     model::{
         requests::{
-            ConnectRequest, FundchannelRequest, GetinfoRequest, InvoiceRequest,
-            ListchannelsRequest, ListfundsRequest, ListinvoicesRequest, ListpaysRequest,
-            ListtransactionsRequest, NewaddrRequest, PayRequest,
+            CloseRequest, ConnectRequest, DecodeRequest, DisconnectRequest, FetchinvoiceRequest,
+            FundchannelRequest, GetinfoRequest, GetrouteRequest, InvoiceRequest, KeysendRequest,
+            ListchannelsRequest, ListclosedchannelsRequest, ListforwardsRequest,
+            ListforwardsStatus, ListfundsRequest, ListinvoicesIndex, ListinvoicesRequest,
+            ListnodesRequest, ListpaysRequest, ListpaysStatus, ListpeerchannelsRequest,
+            ListpeersRequest, ListtransactionsRequest, NewaddrAddresstype, NewaddrRequest,
+            OfferRequest, PayRequest, PluginRequest, PluginSubcommand, RenepayRequest,
+            SendpayRequest, SendpayRoute, SetchannelRequest, WaitanyinvoiceRequest,
+            WaitblockheightRequest, WaitsendpayRequest, WithdrawRequest,
         },
         responses::{
-            GetinfoResponse, ListchannelsResponse, ListfundsOutputsStatus,
-            ListinvoicesInvoicesStatus, ListpaysPaysStatus,
+            DecodeResponse, GetinfoResponse, ListchannelsResponse, ListfundsOutputsStatus,
+            ListinvoicesInvoicesStatus, ListpaysPaysStatus, ListpeerchannelsChannelsHtlcsDirection,
+            ListpeerchannelsChannelsState, ListpeerchannelsResponse, PluginResponse,
         },
     },
-    primitives::{Amount, AmountOrAll, AmountOrAny, PublicKey},
+    primitives::{Amount, AmountOrAll, AmountOrAny, Outpoint, PublicKey, Sha256, ShortChannelId},
     ClnRpc,
 };
-use tokio::{sync::Mutex, time::sleep};
+use futures_util::{stream, Stream};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Instant},
+};
 
-use crate::{hex, InvoiceStatus};
+use crate::{bail, hex, InvoiceStatus, RegtestError, Result};
 
 use super::{
-    types::{Balance, ConnectInfo},
-    LightningClient,
+    parse_version,
+    types::{
+        AddressType, Balance, ChannelEdge, ChannelHtlcState, ChannelInfo, ChannelPolicy,
+        ChannelStats, ChannelType, CloseTransaction, ConnectInfo, DecodedInvoice, ForwardingEvent,
+        HtlcInfo, HtlcSnapshot, InvoiceInfo, InvoiceRecord, ListChannelsFilter, NetworkGraph,
+        NodeHealth, NodeInfo, OnChainTx, PaymentRecord, PeerInfo, PendingChannelInfo, RouteHop,
+        Utxo, WaitConfig,
+    },
+    LightningClient, REBALANCE_FINAL_CLTV,
 };
 
+/// Invoice with optional metadata, created with [`InvoiceBuilder::build`]
+#[derive(Debug, Clone, Default)]
+pub struct InvoiceBuilder {
+    amount_msat: Option<u64>,
+    description: Option<String>,
+    expiry_secs: Option<u64>,
+    preimage: Option<String>,
+    label: Option<String>,
+}
+
+impl InvoiceBuilder {
+    /// Create new [`InvoiceBuilder`] for an amountless invoice
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Invoice amount
+    pub fn amount_msat(mut self, amount_msat: u64) -> Self {
+        self.amount_msat = Some(amount_msat);
+        self
+    }
+
+    /// Invoice description
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Seconds until the invoice expires
+    pub fn expiry_secs(mut self, expiry_secs: u64) -> Self {
+        self.expiry_secs = Some(expiry_secs);
+        self
+    }
+
+    /// Hex encoded preimage, CLN generates one if not set
+    pub fn preimage(mut self, preimage: &str) -> Self {
+        self.preimage = Some(preimage.to_string());
+        self
+    }
+
+    /// Invoice label, a prefixed random label is used if not set
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Create invoice on node
+    ///
+    /// Returns the bolt11
+    pub async fn build(&self, client: &ClnClient) -> Result<String> {
+        let label = self
+            .label
+            .clone()
+            .unwrap_or_else(|| format!("{}{}", client.label_prefix, uuid::Uuid::new_v4()));
+
+        let amount_msat = match self.amount_msat {
+            Some(amount) => AmountOrAny::Amount(Amount::from_msat(amount)),
+            None => AmountOrAny::Any,
+        };
+
+        let cln_response = client
+            .client
+            .lock()
+            .await
+            .call(cln_rpc::Request::Invoice(InvoiceRequest {
+                amount_msat,
+                description: self.description.clone().unwrap_or_default(),
+                label,
+                expiry: self.expiry_secs,
+                fallbacks: None,
+                preimage: self.preimage.clone(),
+                cltv: None,
+                deschashonly: None,
+                exposeprivatechannels: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::Invoice(invoice_res) => Ok(invoice_res.bolt11),
+            _ => {
+                bail!("Wrong cln response");
+            }
+        }
+    }
+}
+
 /// Cln
+#[derive(Clone)]
 pub struct ClnClient {
     client: Arc<Mutex<ClnRpc>>,
     pub rpc_path: PathBuf,
+    label_prefix: String,
+    short_node_id: String,
 }
 
 impl ClnClient {
@@ -41,14 +154,32 @@ impl ClnClient {
 
         println!("rpc_path: {}", rpc_path.display());
 
-        let cln_client = cln_rpc::ClnRpc::new(&rpc_path).await?;
+        let mut cln_client = cln_rpc::ClnRpc::new(&rpc_path).await?;
+
+        let short_node_id = match cln_client.call(GetinfoRequest {}.into()).await? {
+            cln_rpc::Response::Getinfo(info_response) => short_id(&info_response.id.to_string()),
+            _ => bail!("CLN returned wrong response kind"),
+        };
 
         Ok(Self {
             rpc_path,
             client: Arc::new(Mutex::new(cln_client)),
+            label_prefix: String::new(),
+            short_node_id,
         })
     }
 
+    /// Short node identifier for logs
+    pub fn short_node_id(&self) -> &str {
+        &self.short_node_id
+    }
+
+    /// Prefix invoice labels to avoid collisions between tests sharing a node
+    pub fn with_label_prefix(mut self, prefix: &str) -> Self {
+        self.label_prefix = prefix.to_string();
+        self
+    }
+
     /// Get node info
     pub async fn get_info(&self) -> Result<GetinfoResponse> {
         let client = &self.client;
@@ -63,39 +194,952 @@ impl ClnClient {
         }
     }
 
-    pub async fn list_transactions(&self) -> Result<()> {
+    /// Get node alias, or the short node id if no alias is set
+    pub async fn get_node_alias(&self) -> Result<String> {
+        let info = self.get_info().await?;
+
+        match info.alias.filter(|alias| !alias.is_empty()) {
+            Some(alias) => Ok(alias),
+            None => Ok(short_id(&info.id.to_string())),
+        }
+    }
+
+    /// Get node alias
+    pub async fn get_alias(&self) -> Result<Option<String>> {
+        let info = self.get_info().await?;
+
+        Ok(info.alias.filter(|alias| !alias.is_empty()))
+    }
+
+    /// Get CLN version as (major, minor, patch)
+    pub async fn get_version(&self) -> Result<(u32, u32, u32)> {
+        let info = self.get_info().await?;
+
+        parse_version(&info.version)
+    }
+
+    /// Error if CLN version is older than the minimum
+    ///
+    /// [`ClnClient::renepay`] and [`ClnClient::get_close_transaction`] check their minimum
+    /// version with this. Splicing is not wrapped by this client so has no gate
+    pub async fn assert_min_cln_version(&self, major: u32, minor: u32, patch: u32) -> Result<()> {
+        let version = self.get_version().await?;
+
+        if version < (major, minor, patch) {
+            bail!(
+                "CLN version {}.{}.{} is older than required {}.{}.{}",
+                version.0,
+                version.1,
+                version.2,
+                major,
+                minor,
+                patch
+            );
+        }
+
+        Ok(())
+    }
+
+    /// List on chain wallet transactions
+    ///
+    /// The amount is matched against wallet outputs from `listfunds`
+    pub async fn list_transactions(&self) -> Result<Vec<OnChainTx>> {
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::ListTransactions(
+                ListtransactionsRequest {},
+            ))
+            .await?;
+
+        let transactions = match cln_response {
+            cln_rpc::Response::ListTransactions(response) => response.transactions,
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::ListFunds(ListfundsRequest {
+                spent: Some(true),
+            }))
+            .await?;
+
+        let wallet_outputs: HashMap<(String, u32), u64> = match cln_response {
+            cln_rpc::Response::ListFunds(response) => response
+                .outputs
+                .into_iter()
+                .map(|o| ((o.txid.to_string(), o.output), o.amount_msat.msat()))
+                .collect(),
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        let tip_height = match cln_client.call(GetinfoRequest {}.into()).await? {
+            cln_rpc::Response::Getinfo(info_response) => info_response.blockheight,
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        let transactions = transactions
+            .into_iter()
+            .map(|tx| {
+                let txid = tx.hash.to_string();
+
+                let received: u64 = tx
+                    .outputs
+                    .iter()
+                    .filter_map(|o| wallet_outputs.get(&(txid.clone(), o.index)))
+                    .sum();
+                let spent: u64 = tx
+                    .inputs
+                    .iter()
+                    .filter_map(|i| wallet_outputs.get(&(i.txid.to_string(), i.index)))
+                    .sum();
+
+                // Unconfirmed transactions have a block height of 0
+                let block_height = (tx.blockheight > 0).then_some(tx.blockheight);
+
+                OnChainTx {
+                    txid,
+                    block_height,
+                    amount_msat: received as i64 - spent as i64,
+                    confirms: block_height.map_or(0, |h| tip_height.saturating_sub(h) + 1),
+                }
+            })
+            .collect();
+
+        Ok(transactions)
+    }
+
+    pub async fn list_channels(&self, filter: ListChannelsFilter) -> Result<ListchannelsResponse> {
+        let source = filter.source.as_deref().map(parse_pubkey).transpose()?;
+        let destination = filter
+            .destination
+            .as_deref()
+            .map(parse_pubkey)
+            .transpose()?;
+        let short_channel_id = filter
+            .scid
+            .map(|scid| ShortChannelId::from_str(&scid))
+            .transpose()?;
+
+        let mut cln_client = self.client.lock().await;
+        let cln_response = cln_client
+            .call(cln_rpc::Request::ListChannels(ListchannelsRequest {
+                destination,
+                short_channel_id,
+                source,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::ListChannels(channels) => Ok(channels),
+            _ => {
+                bail!("Wrong cln response");
+            }
+        }
+    }
+
+    /// List channels with node as the source
+    pub async fn list_channels_from(&self, node_id: &str) -> Result<ListchannelsResponse> {
+        self.list_channels(ListChannelsFilter {
+            source: Some(node_id.to_string()),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// List channels with node as the destination
+    pub async fn list_channels_to(&self, node_id: &str) -> Result<ListchannelsResponse> {
+        self.list_channels(ListChannelsFilter {
+            destination: Some(node_id.to_string()),
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn list_peer_channels(&self) -> Result<ListpeerchannelsResponse> {
+        let mut cln_client = self.client.lock().await;
+        let cln_response = cln_client
+            .call(cln_rpc::Request::ListPeerChannels(
+                ListpeerchannelsRequest { id: None },
+            ))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::ListPeerChannels(channels) => Ok(channels),
+            _ => {
+                bail!("Wrong cln response");
+            }
+        }
+    }
+
+    /// Get statistics of each channel
+    ///
+    /// Fee income is the sum of fees of settled forwards out of the channel
+    pub async fn get_channel_stats(&self) -> Result<Vec<ChannelStats>> {
+        let block_height = self.get_info().await?.blockheight;
+
+        let forwards = {
+            let mut cln_client = self.client.lock().await;
+
+            let cln_response = cln_client
+                .call(cln_rpc::Request::ListForwards(ListforwardsRequest {
+                    status: Some(ListforwardsStatus::SETTLED),
+                    in_channel: None,
+                    out_channel: None,
+                    index: None,
+                    start: None,
+                    limit: None,
+                }))
+                .await?;
+
+            match cln_response {
+                cln_rpc::Response::ListForwards(forwards_response) => forwards_response.forwards,
+                _ => {
+                    bail!("Wrong cln response");
+                }
+            }
+        };
+
+        let mut fee_income: HashMap<String, u64> = HashMap::new();
+
+        for forward in forwards {
+            if let (Some(out_channel), Some(fee)) = (forward.out_channel, forward.fee_msat) {
+                *fee_income.entry(out_channel.to_string()).or_default() += fee.msat();
+            }
+        }
+
+        let channels = self.list_peer_channels().await?;
+
+        let stats = channels
+            .channels
+            .into_iter()
+            .map(|channel| {
+                let scid = channel.short_channel_id;
+
+                ChannelStats {
+                    channel_id: channel
+                        .channel_id
+                        .map(|id| id.to_string())
+                        .unwrap_or_default(),
+                    peer_id: channel.peer_id.map(|id| id.to_string()).unwrap_or_default(),
+                    age_blocks: scid
+                        .map(|scid| block_height.saturating_sub(scid.block()))
+                        .unwrap_or_default(),
+                    in_payments_offered: channel.in_payments_offered.unwrap_or_default(),
+                    in_payments_fulfilled: channel.in_payments_fulfilled.unwrap_or_default(),
+                    in_msatoshi_fulfilled: channel
+                        .in_fulfilled_msat
+                        .map(|a| a.msat())
+                        .unwrap_or_default(),
+                    out_payments_offered: channel.out_payments_offered.unwrap_or_default(),
+                    out_payments_fulfilled: channel.out_payments_fulfilled.unwrap_or_default(),
+                    fee_income_msat: scid
+                        .and_then(|scid| fee_income.get(&scid.to_string()).copied())
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        Ok(stats)
+    }
+
+    /// Wait for channel to reach `CHANNELD_NORMAL`
+    pub async fn wait_for_channel_ready(&self, channel_id: &str, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            let channels = self.list_peer_channels().await?;
+
+            let channel = channels
+                .channels
+                .iter()
+                .find(|c| c.channel_id.map(|id| id.to_string()).as_deref() == Some(channel_id));
+
+            if let Some(channel) = channel {
+                if matches!(
+                    channel.state,
+                    Some(ListpeerchannelsChannelsState::CHANNELD_NORMAL)
+                ) {
+                    tracing::info!("CLN channel {} ready", channel_id);
+                    return Ok(());
+                }
+            }
+
+            sleep(Duration::from_secs(2)).await;
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    /// Count htlcs not yet settled or failed across all channels
+    pub async fn count_pending_htlcs(&self) -> Result<u32> {
+        let channels = self.list_peer_channels().await?;
+
+        let count = channels
+            .channels
+            .iter()
+            .map(|c| c.htlcs.as_ref().map_or(0, |htlcs| htlcs.len()))
+            .sum::<usize>();
+
+        Ok(count as u32)
+    }
+
+    /// Snapshot of all htlcs in flight, split by direction per channel
+    pub async fn snapshot_htlc_state(&self) -> Result<HtlcSnapshot> {
+        let channels = self.list_peer_channels().await?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(anyhow::Error::from)?
+            .as_secs();
+
+        let channels = channels
+            .channels
+            .into_iter()
+            .map(|c| {
+                let mut state = ChannelHtlcState {
+                    channel_id: c.channel_id.map(|id| id.to_string()).unwrap_or_default(),
+                    ..Default::default()
+                };
+
+                for htlc in c.htlcs.unwrap_or_default() {
+                    let info = HtlcInfo {
+                        id: htlc.id.unwrap_or_default(),
+                        payment_hash: htlc.payment_hash.map(|h| h.to_string()).unwrap_or_default(),
+                        amount_msat: htlc.amount_msat.map_or(0, |a| a.msat()),
+                        expiry: htlc.expiry.unwrap_or_default(),
+                    };
+
+                    match htlc.direction {
+                        Some(ListpeerchannelsChannelsHtlcsDirection::OUT) => {
+                            state.offered_htlcs.push(info)
+                        }
+                        _ => state.received_htlcs.push(info),
+                    }
+                }
+
+                state
+            })
+            .collect();
+
+        Ok(HtlcSnapshot {
+            timestamp,
+            channels,
+        })
+    }
+
+    /// Decode bolt11 or bolt12 string
+    async fn decode(&self, string: &str) -> Result<DecodeResponse> {
+        let cln_response = self
+            .client
+            .lock()
+            .await
+            .call(cln_rpc::Request::Decode(DecodeRequest {
+                string: string.to_string(),
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::Decode(decode_response) => Ok(decode_response),
+            _ => bail!("CLN returned wrong response kind"),
+        }
+    }
+
+    /// Wait until no htlcs are pending, for use in test teardown
+    pub async fn assert_no_pending_htlcs(&self, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            let count = self.count_pending_htlcs().await?;
+
+            if count == 0 {
+                return Ok(());
+            }
+
+            tracing::debug!("CLN has {} pending htlcs", count);
+            sleep(Duration::from_secs(2)).await;
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    /// Wait for node to reach block height
+    ///
+    /// Uses its own rpc connection so other calls are not blocked while waiting
+    pub async fn wait_block_height(&self, height: u32, timeout: Duration) -> Result<()> {
+        let mut cln_client = ClnRpc::new(&self.rpc_path).await?;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::WaitBlockHeight(WaitblockheightRequest {
+                blockheight: height,
+                timeout: Some(timeout.as_secs() as u32),
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::WaitBlockHeight(height_response) => {
+                tracing::debug!("CLN reached block height: {}", height_response.blockheight);
+                Ok(())
+            }
+            _ => {
+                bail!("Wrong cln response");
+            }
+        }
+    }
+
+    /// Open zero conf channel to peer
+    pub async fn open_channel_zeroconf(&self, amount_sat: u64, peer_id: &str) -> Result<String> {
         let client = &self.client;
 
         let cln_response = client
             .lock()
             .await
-            .call(cln_rpc::Request::ListTransactions(
-                ListtransactionsRequest {},
+            .call(cln_rpc::Request::FundChannel(FundchannelRequest {
+                amount: AmountOrAll::Amount(Amount::from_sat(amount_sat)),
+                id: parse_pubkey(peer_id)?,
+                push_msat: None,
+                announce: Some(false),
+                close_to: None,
+                compact_lease: None,
+                feerate: None,
+                minconf: None,
+                mindepth: Some(0),
+                request_amt: None,
+                reserve: None,
+                channel_type: None,
+                utxos: None,
+            }))
+            .await?;
+
+        let channel_id = match cln_response {
+            cln_rpc::Response::FundChannel(addr_res) => addr_res.channel_id,
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        tracing::info!("CLN opened zero conf channel: {}", channel_id);
+
+        Ok(channel_id.to_string())
+    }
+
+    /// Get the last commitment transaction of a closed channel
+    ///
+    /// Requires `listclosedchannels` from CLN 23.05
+    pub async fn get_close_transaction(
+        &self,
+        channel_id: &str,
+    ) -> Result<Option<CloseTransaction>> {
+        let (major, minor, patch) = MIN_LISTCLOSEDCHANNELS_VERSION;
+        self.assert_min_cln_version(major, minor, patch).await?;
+
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::ListClosedChannels(
+                ListclosedchannelsRequest { id: None },
             ))
             .await?;
 
-        println!("{:#?}", cln_response);
+        let closed_channels = match cln_response {
+            cln_rpc::Response::ListClosedChannels(closed_response) => {
+                closed_response.closedchannels
+            }
+            _ => {
+                bail!("Wrong cln response");
+            }
+        };
+
+        let closed_channel = closed_channels
+            .into_iter()
+            .find(|c| c.channel_id.to_string() == channel_id);
+
+        let closed_channel = match closed_channel {
+            Some(closed_channel) => closed_channel,
+            None => return Ok(None),
+        };
+
+        let txid = closed_channel
+            .last_commitment_txid
+            .ok_or(anyhow!("Closed channel has no commitment tx"))?;
+
+        // listclosedchannels does not report the feerate range of the close
+        Ok(Some(CloseTransaction {
+            txid: txid.to_string(),
+            close_type: format!("{:?}", closed_channel.close_cause),
+            final_to_us_msat: closed_channel.final_to_us_msat.msat(),
+            min_possible_feerate_per_kw: None,
+            max_possible_feerate_per_kw: None,
+        }))
+    }
+
+    async fn plugin(
+        &self,
+        subcommand: PluginSubcommand,
+        plugin: Option<String>,
+    ) -> Result<PluginResponse> {
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::Plugin(PluginRequest {
+                subcommand,
+                plugin,
+                directory: None,
+                options: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::Plugin(plugin_response) => Ok(plugin_response),
+            _ => {
+                bail!("Wrong cln response");
+            }
+        }
+    }
+
+    /// Start dynamic plugin
+    pub async fn start_plugin(&self, plugin_path: &Path) -> Result<()> {
+        let file_name = plugin_path.file_name();
+        let plugin_path = plugin_path.display().to_string();
+
+        let response = self
+            .plugin(PluginSubcommand::START, Some(plugin_path.clone()))
+            .await?;
+
+        let plugin = response
+            .plugins
+            .unwrap_or_default()
+            .into_iter()
+            .find(|p| Path::new(&p.name).file_name() == file_name)
+            .ok_or(anyhow!("Plugin {} did not start", plugin_path))?;
+
+        if !plugin.dynamic {
+            bail!("Plugin {} is not dynamic", plugin_path);
+        }
+
+        tracing::info!("CLN started plugin: {}", plugin_path);
+
+        Ok(())
+    }
+
+    /// Stop dynamic plugin
+    pub async fn stop_plugin(&self, plugin_name: &str) -> Result<()> {
+        let file_name = Path::new(plugin_name).file_name();
+
+        let response = self.plugin(PluginSubcommand::LIST, None).await?;
+
+        let plugin = response
+            .plugins
+            .unwrap_or_default()
+            .into_iter()
+            .find(|p| Path::new(&p.name).file_name() == file_name)
+            .ok_or(RegtestError::NotFound)?;
+
+        if !plugin.dynamic {
+            bail!("Plugin {} is not dynamic", plugin_name);
+        }
+
+        self.plugin(PluginSubcommand::STOP, Some(plugin.name))
+            .await?;
+
+        tracing::info!("CLN stopped plugin: {}", plugin_name);
 
         Ok(())
     }
 
-    pub async fn list_channels(&self) -> Result<ListchannelsResponse> {
+    /// Withdraw onchain spending only the given `txid:vout` utxos
+    ///
+    /// Returns the txid
+    pub async fn withdraw_with_utxos(
+        &self,
+        destination: &str,
+        amount_sat: u64,
+        utxos: Vec<String>,
+    ) -> Result<String> {
+        let utxos = utxos
+            .iter()
+            .map(|utxo| {
+                let (txid, outnum) = utxo
+                    .split_once(':')
+                    .ok_or(anyhow!("Invalid outpoint {}", utxo))?;
+
+                Ok(Outpoint {
+                    txid: Sha256::from_str(txid).map_err(anyhow::Error::from)?,
+                    outnum: outnum.parse()?,
+                })
+            })
+            .collect::<Result<Vec<Outpoint>>>()?;
+
         let mut cln_client = self.client.lock().await;
+
         let cln_response = cln_client
-            .call(cln_rpc::Request::ListChannels(ListchannelsRequest {
-                destination: None,
-                short_channel_id: None,
-                source: None,
+            .call(cln_rpc::Request::Withdraw(WithdrawRequest {
+                destination: destination.to_string(),
+                satoshi: AmountOrAll::Amount(Amount::from_sat(amount_sat)),
+                feerate: None,
+                minconf: None,
+                utxos: Some(utxos),
             }))
             .await?;
 
         match cln_response {
-            cln_rpc::Response::ListChannels(channels) => Ok(channels),
+            cln_rpc::Response::Withdraw(withdraw_response) => Ok(withdraw_response.txid),
+            _ => {
+                bail!("Wrong cln response");
+            }
+        }
+    }
+
+    /// Create bolt12 offer
+    pub async fn create_offer(
+        &self,
+        amount_msat: Option<u64>,
+        description: &str,
+    ) -> Result<String> {
+        let mut cln_client = self.client.lock().await;
+
+        let amount = match amount_msat {
+            Some(amount) => format!("{}msat", amount),
+            None => "any".to_string(),
+        };
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::Offer(OfferRequest {
+                amount,
+                description: Some(description.to_string()),
+                issuer: None,
+                label: None,
+                quantity_max: None,
+                absolute_expiry: None,
+                recurrence: None,
+                recurrence_base: None,
+                recurrence_paywindow: None,
+                recurrence_limit: None,
+                single_use: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::Offer(offer_response) => Ok(offer_response.bolt12),
             _ => {
                 bail!("Wrong cln response");
             }
         }
     }
+
+    /// Fetch bolt12 invoice from offer
+    pub async fn fetch_invoice_from_offer(&self, offer: &str, amount_msat: u64) -> Result<String> {
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::FetchInvoice(FetchinvoiceRequest {
+                offer: offer.to_string(),
+                amount_msat: Some(Amount::from_msat(amount_msat)),
+                quantity: None,
+                recurrence_counter: None,
+                recurrence_start: None,
+                recurrence_label: None,
+                timeout: None,
+                payer_note: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::FetchInvoice(fetch_response) => Ok(fetch_response.invoice),
+            _ => {
+                bail!("Wrong cln response");
+            }
+        }
+    }
+
+    /// Pay bolt12 offer
+    ///
+    /// Returns the payment preimage
+    pub async fn pay_offer(&self, offer: &str, amount_msat: u64) -> Result<String> {
+        let invoice = self.fetch_invoice_from_offer(offer, amount_msat).await?;
+
+        self.pay_invoice(invoice, None).await
+    }
+
+    /// Stream of invoices as they are paid
+    ///
+    /// Starts after `start_index` pay index, or with the next paid invoice if not set.
+    /// Uses its own rpc connection as `waitanyinvoice` blocks until an invoice is paid.
+    pub async fn invoice_payment_stream(
+        &self,
+        start_index: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<InvoiceInfo>>> {
+        let client = ClnRpc::new(&self.rpc_path).await?;
+
+        // State is `None` once the stream has returned an error
+        let stream = stream::unfold(Some((client, start_index)), |state| async move {
+            let (mut client, lastpay_index) = state?;
+
+            let cln_response = client
+                .call(cln_rpc::Request::WaitAnyInvoice(WaitanyinvoiceRequest {
+                    lastpay_index,
+                    timeout: None,
+                }))
+                .await;
+
+            match cln_response {
+                Ok(cln_rpc::Response::WaitAnyInvoice(invoice)) => {
+                    let next_index = invoice.pay_index.or(lastpay_index);
+
+                    let invoice_info = InvoiceInfo {
+                        label: invoice.label,
+                        payment_hash: invoice.payment_hash.to_string(),
+                        bolt11: invoice.bolt11,
+                        amount_received_msat: invoice.amount_received_msat.map(|a| a.msat()),
+                        pay_index: invoice.pay_index,
+                    };
+
+                    Some((Ok(invoice_info), Some((client, next_index))))
+                }
+                Ok(_) => Some((Err(anyhow!("Wrong cln response").into()), None)),
+                Err(err) => Some((Err(err.into()), None)),
+            }
+        });
+
+        Ok(stream)
+    }
+
+    /// Pay bolt11 invoice using renepay
+    ///
+    /// Requires CLN 23.11
+    pub async fn renepay(&self, bolt11: String, max_fee_msat: Option<u64>) -> Result<String> {
+        let (major, minor, patch) = MIN_RENEPAY_VERSION;
+        self.assert_min_cln_version(major, minor, patch).await?;
+
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::RenePay(RenepayRequest {
+                invstring: bolt11,
+                amount_msat: None,
+                maxfee: max_fee_msat.map(Amount::from_msat),
+                maxdelay: None,
+                retry_for: None,
+                description: None,
+                label: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::RenePay(pay_response) => {
+                Ok(hex::encode(pay_response.payment_preimage.to_vec()))
+            }
+            _ => {
+                bail!("CLN returned wrong response kind");
+            }
+        }
+    }
+
+    /// Wait for zero conf channel to be usable without confirmations
+    pub async fn wait_for_channel_usable_zeroconf(&self, channel_id: &str) -> Result<()> {
+        self.wait_for_channel_ready(channel_id, Duration::from_secs(200))
+            .await
+    }
+
+    /// Connect to peer and open a channel to it
+    ///
+    /// The connect and open are retried if the peer disconnects in between.
+    /// Returns the channel id
+    pub async fn connect_and_open_channel(
+        &self,
+        connect_info: &ConnectInfo,
+        amount_sat: u64,
+        push_amount: Option<u64>,
+    ) -> Result<String> {
+        let max_attempts = 3;
+        let mut attempt = 1;
+
+        loop {
+            self.connect_peer(
+                connect_info.pubkey.clone(),
+                connect_info.address.clone(),
+                connect_info.port,
+            )
+            .await?;
+
+            match self
+                .fund_channel(
+                    amount_sat,
+                    &connect_info.pubkey,
+                    push_amount,
+                    true,
+                    None,
+                    None,
+                )
+                .await
+            {
+                Ok(channel_id) => return Ok(channel_id),
+                Err(RegtestError::RpcError(err))
+                    if attempt < max_attempts && is_peer_disconnected(&err) =>
+                {
+                    tracing::warn!("CLN peer disconnected before open, retrying: {}", err);
+                    attempt += 1;
+                    sleep(Duration::from_secs(1)).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Pay bolt11 invoice, amount is required for invoices without one
+    async fn pay(
+        &self,
+        bolt11: String,
+        amount_msat: Option<u64>,
+        max_fee_msat: Option<u64>,
+    ) -> Result<String> {
+        // Zero fee limit is set as maxfeepercent without the default exempt fee
+        let (maxfee, maxfeepercent, exemptfee) = match max_fee_msat {
+            Some(0) => (None, Some(0.0), Some(Amount::from_msat(0))),
+            max_fee_msat => (max_fee_msat.map(Amount::from_msat), None, None),
+        };
+
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::Pay(PayRequest {
+                bolt11,
+                amount_msat: amount_msat.map(Amount::from_msat),
+                label: None,
+                riskfactor: None,
+                maxfeepercent,
+                retry_for: None,
+                maxdelay: None,
+                exemptfee,
+                localinvreqid: None,
+                exclude: None,
+                maxfee,
+                description: None,
+                partial_msat: None,
+            }))
+            .await?;
+
+        let response = match cln_response {
+            cln_rpc::Response::Pay(pay_response) => {
+                Ok(hex::encode(pay_response.payment_preimage.to_vec()))
+            }
+            _ => {
+                bail!("CLN returned wrong response kind");
+            }
+        };
+
+        // match return_error {
+        //     true => {
+        //         bail!("Lighiting error");
+        //     }
+        //     false => response,
+        // }
+
+        response
+    }
+
+    /// Channels of route between two other nodes as (node, channel into node)
+    async fn route_between(
+        &self,
+        source: &str,
+        destination: &str,
+        amount_msat: u64,
+        exclude_node: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::GetRoute(GetrouteRequest {
+                id: parse_pubkey(destination)?,
+                amount_msat: Amount::from_msat(amount_msat),
+                riskfactor: 1,
+                cltv: None,
+                fromid: Some(parse_pubkey(source)?),
+                fuzzpercent: None,
+                exclude: Some(vec![exclude_node.to_string()]),
+                maxhops: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::GetRoute(route_response) => Ok(route_response
+                .route
+                .into_iter()
+                .map(|hop| (hop.id.to_string(), hop.channel.to_string()))
+                .collect()),
+            _ => bail!("CLN returned wrong response kind"),
+        }
+    }
+
+    /// Base fee msat, fee ppm and cltv delta of channel in direction from source
+    async fn channel_policy(
+        &self,
+        short_channel_id: &str,
+        source: &str,
+    ) -> Result<(u64, u64, u32)> {
+        let channels = self
+            .list_channels(ListChannelsFilter {
+                scid: Some(short_channel_id.to_string()),
+                ..Default::default()
+            })
+            .await?;
+
+        let channel = channels
+            .channels
+            .into_iter()
+            .find(|c| c.source.to_string() == source)
+            .ok_or(RegtestError::NotFound)?;
+
+        Ok((
+            channel.base_fee_millisatoshi as u64,
+            channel.fee_per_millionth as u64,
+            channel.delay,
+        ))
+    }
+
+    async fn fund_channel(
+        &self,
+        amount_sat: u64,
+        peer_id: &str,
+        push_amount: Option<u64>,
+        announce: bool,
+        channel_type: Option<ChannelType>,
+        minconf: Option<u32>,
+    ) -> Result<String> {
+        // Channel type feature bits, anchors build on static remote key
+        let channel_type = channel_type.map(|channel_type| match channel_type {
+            ChannelType::LegacyStaticKey => vec![12],
+            ChannelType::AnchorOutputs => vec![12, 20],
+            ChannelType::AnchorZeroFeeHtlcTx => vec![12, 22],
+        });
+
+        let client = &self.client;
+
+        let cln_response = client
+            .lock()
+            .await
+            .call(cln_rpc::Request::FundChannel(FundchannelRequest {
+                amount: AmountOrAll::Amount(Amount::from_sat(amount_sat)),
+                id: parse_pubkey(peer_id)?,
+                push_msat: push_amount.map(Amount::from_sat),
+                announce: Some(announce),
+                close_to: None,
+                compact_lease: None,
+                feerate: None,
+                minconf,
+                mindepth: None,
+                request_amt: None,
+                reserve: None,
+                channel_type,
+                utxos: None,
+            }))
+            .await?;
+
+        let channel_id = match cln_response {
+            cln_rpc::Response::FundChannel(addr_res) => addr_res.channel_id,
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        tracing::info!("CLN opened channel: {}", channel_id);
+
+        Ok(channel_id.to_string())
+    }
 }
 
 #[async_trait]
@@ -127,28 +1171,99 @@ impl LightningClient for ClnClient {
             pubkey: response.id.to_string(),
             address,
             port: *port,
+            alias: response.alias.filter(|alias| !alias.is_empty()),
         })
     }
 
-    async fn get_new_onchain_address(&self) -> Result<String> {
+    async fn get_new_onchain_address(&self, addr_type: Option<AddressType>) -> Result<String> {
         let client = &self.client;
 
+        let addresstype = match addr_type {
+            None => None,
+            Some(AddressType::P2wpkh) => Some(NewaddrAddresstype::BECH32),
+            Some(AddressType::P2tr) => Some(NewaddrAddresstype::P2TR),
+            Some(AddressType::P2shP2wpkh) => bail!("CLN does not support p2sh-p2wpkh addresses"),
+        };
+
         let cln_response = client
             .lock()
             .await
-            .call(cln_rpc::Request::NewAddr(NewaddrRequest {
-                addresstype: None,
+            .call(cln_rpc::Request::NewAddr(NewaddrRequest { addresstype }))
+            .await?;
+
+        let address = match cln_response {
+            cln_rpc::Response::NewAddr(addr_res) => match addr_type {
+                Some(AddressType::P2tr) => addr_res.p2tr.ok_or(anyhow!("No p2tr".to_string()))?,
+                _ => addr_res.bech32.ok_or(anyhow!("No bech32".to_string()))?,
+            },
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        Ok(address)
+    }
+
+    async fn send_on_chain(&self, address: &str, amount_sat: u64) -> Result<String> {
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::Withdraw(WithdrawRequest {
+                destination: address.to_string(),
+                satoshi: AmountOrAll::Amount(Amount::from_sat(amount_sat)),
+                feerate: None,
+                minconf: None,
+                utxos: None,
             }))
             .await?;
 
-        let address = match cln_response {
-            cln_rpc::Response::NewAddr(addr_res) => {
-                &addr_res.bech32.ok_or(anyhow!("No bech32".to_string()))?
-            }
+        match cln_response {
+            cln_rpc::Response::Withdraw(withdraw_response) => Ok(withdraw_response.txid),
+            _ => bail!("CLN returned wrong response kind"),
+        }
+    }
+
+    async fn list_onchain_transactions(&self) -> Result<Vec<OnChainTx>> {
+        self.list_transactions().await
+    }
+
+    async fn list_utxos(&self) -> Result<Vec<Utxo>> {
+        let mut cln_client = self.client.lock().await;
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::ListFunds(ListfundsRequest {
+                spent: None,
+            }))
+            .await?;
+
+        let outputs = match cln_response {
+            cln_rpc::Response::ListFunds(response) => response.outputs,
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        let tip_height = match cln_client.call(GetinfoRequest {}.into()).await? {
+            cln_rpc::Response::Getinfo(info_response) => info_response.blockheight,
             _ => bail!("CLN returned wrong response kind"),
         };
 
-        Ok(address.to_string())
+        Ok(outputs
+            .into_iter()
+            .filter(|o| o.status != ListfundsOutputsStatus::SPENT)
+            .map(|o| {
+                let confirmations = o
+                    .blockheight
+                    .filter(|height| *height > 0)
+                    .map(|height| tip_height.saturating_sub(height) + 1)
+                    .unwrap_or_default();
+
+                Utxo {
+                    txid: o.txid.to_string(),
+                    vout: o.output,
+                    amount_sat: o.amount_msat.msat() / 1000,
+                    address: o.address.unwrap_or_default(),
+                    confirmations,
+                    spendable: o.status == ListfundsOutputsStatus::CONFIRMED && !o.reserved,
+                }
+            })
+            .collect())
     }
 
     async fn connect_peer(&self, pubkey: String, addr: String, port: u16) -> Result<()> {
@@ -174,44 +1289,131 @@ impl LightningClient for ClnClient {
         Ok(())
     }
 
+    async fn disconnect_peer(&self, pubkey: &str) -> Result<()> {
+        let client = &self.client;
+
+        let cln_response = client
+            .lock()
+            .await
+            .call(cln_rpc::Request::Disconnect(DisconnectRequest {
+                id: parse_pubkey(pubkey)?,
+                force: Some(false),
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::Disconnect(_) => (),
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        tracing::debug!("CLN disconnected from peer: {}", pubkey);
+
+        Ok(())
+    }
+
+    async fn list_peers(&self) -> Result<Vec<PeerInfo>> {
+        let peers = {
+            let mut cln_client = self.client.lock().await;
+
+            let cln_response = cln_client
+                .call(cln_rpc::Request::ListPeers(ListpeersRequest {
+                    id: None,
+                    level: None,
+                }))
+                .await?;
+
+            match cln_response {
+                cln_rpc::Response::ListPeers(peers_response) => peers_response.peers,
+                _ => bail!("CLN returned wrong response kind"),
+            }
+        };
+
+        let channels = self.list_peer_channels().await?.channels;
+
+        let peers = peers
+            .into_iter()
+            .map(|peer| PeerInfo {
+                pubkey: peer.id.to_string(),
+                connected: peer.connected,
+                channels: channels
+                    .iter()
+                    .filter(|c| c.peer_id == Some(peer.id))
+                    .count() as u32,
+            })
+            .collect();
+
+        Ok(peers)
+    }
+
     async fn open_channel(
         &self,
         amount_sat: u64,
         peer_id: &str,
         push_amount: Option<u64>,
+        announce: bool,
+        channel_type: Option<ChannelType>,
+        minconf: Option<u32>,
     ) -> Result<()> {
+        self.fund_channel(
+            amount_sat,
+            peer_id,
+            push_amount,
+            announce,
+            channel_type,
+            minconf,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn close_channel(&self, channel_id: &str, force: bool) -> Result<()> {
         let client = &self.client;
 
+        // A timeout of 1 second falls back to a unilateral close
+        let unilateraltimeout = match force {
+            true => Some(1),
+            false => None,
+        };
+
         let cln_response = client
             .lock()
             .await
-            .call(cln_rpc::Request::FundChannel(FundchannelRequest {
-                amount: AmountOrAll::Amount(Amount::from_sat(amount_sat)),
-                id: PublicKey::from_str(peer_id)?,
-                push_msat: push_amount.map(Amount::from_sat),
-                announce: None,
-                close_to: None,
-                compact_lease: None,
-                feerate: None,
-                minconf: None,
-                mindepth: None,
-                request_amt: None,
-                reserve: None,
-                channel_type: None,
-                utxos: None,
+            .call(cln_rpc::Request::Close(CloseRequest {
+                id: channel_id.to_string(),
+                unilateraltimeout,
+                destination: None,
+                fee_negotiation_step: None,
+                wrong_funding: None,
+                force_lease_closed: None,
+                feerange: None,
             }))
             .await?;
 
-        let channel_id = match cln_response {
-            cln_rpc::Response::FundChannel(addr_res) => addr_res.channel_id,
+        let close_type = match cln_response {
+            cln_rpc::Response::Close(close_response) => close_response.item_type,
             _ => bail!("CLN returned wrong response kind"),
         };
 
-        tracing::info!("CLN opened channel: {}", channel_id);
+        tracing::info!("CLN closed channel {}: {:?}", channel_id, close_type);
 
         Ok(())
     }
 
+    async fn health_check(&self) -> Result<NodeHealth> {
+        let info = self.get_info().await?;
+        let balance = self.balance().await?;
+
+        Ok(NodeHealth {
+            chain_synced: info.warning_bitcoind_sync.is_none(),
+            graph_synced: info.warning_lightningd_sync.is_none(),
+            active_channels: info.num_active_channels,
+            pending_channels: info.num_pending_channels,
+            on_chain_balance_sat: balance.on_chain_total / 1000,
+            ln_balance_msat: balance.ln,
+        })
+    }
+
     async fn balance(&self) -> Result<Balance> {
         let client = &self.client;
 
@@ -264,83 +1466,386 @@ impl LightningClient for ClnClient {
         Ok(balance)
     }
 
-    async fn create_invoice(&self, amount_sat: Option<u64>) -> Result<String> {
+    async fn keysend(&self, node_pubkey: &str, amount_msat: u64) -> Result<String> {
         let mut cln_client = self.client.lock().await;
 
-        let label = uuid::Uuid::new_v4().to_string();
+        let cln_response = cln_client
+            .call(cln_rpc::Request::KeySend(KeysendRequest {
+                destination: parse_pubkey(node_pubkey)?,
+                amount_msat: Amount::from_msat(amount_msat),
+                label: None,
+                maxfeepercent: None,
+                retry_for: None,
+                maxdelay: None,
+                exemptfee: None,
+                routehints: None,
+                extratlvs: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::KeySend(keysend_response) => {
+                Ok(hex::encode(keysend_response.payment_preimage.to_vec()))
+            }
+            _ => {
+                bail!("CLN returned wrong response kind");
+            }
+        }
+    }
+
+    async fn get_route(&self, destination: &str, amount_msat: u64) -> Result<Vec<RouteHop>> {
+        let mut cln_client = self.client.lock().await;
 
-        //let amount_msat = AmountOrAny::Amount(Amount::from_sat(amount));
+        let cln_response = cln_client
+            .call(cln_rpc::Request::GetRoute(GetrouteRequest {
+                id: parse_pubkey(destination)?,
+                amount_msat: Amount::from_msat(amount_msat),
+                riskfactor: 1,
+                cltv: None,
+                fromid: None,
+                fuzzpercent: None,
+                exclude: None,
+                maxhops: None,
+            }))
+            .await?;
 
-        let amount_msat = match amount_sat {
-            Some(amount) => AmountOrAny::Amount(Amount::from_sat(amount)),
-            None => AmountOrAny::Any,
+        let route = match cln_response {
+            cln_rpc::Response::GetRoute(route_response) => route_response.route,
+            _ => bail!("CLN returned wrong response kind"),
         };
 
+        // Each hop's amount and delay include those of the hops after it
+        let hops = route
+            .iter()
+            .enumerate()
+            .map(|(i, hop)| {
+                let (next_amount, next_delay) = route
+                    .get(i + 1)
+                    .map(|next| (next.amount_msat.msat(), next.delay))
+                    .unwrap_or((hop.amount_msat.msat(), 0));
+
+                let fee_msat = hop
+                    .amount_msat
+                    .msat()
+                    .checked_sub(next_amount)
+                    .ok_or(anyhow!("CLN route amount increases after hop {}", i))?;
+                let cltv_expiry_delta = hop
+                    .delay
+                    .checked_sub(next_delay)
+                    .ok_or(anyhow!("CLN route delay increases after hop {}", i))?;
+
+                Ok(RouteHop {
+                    pubkey: hop.id.to_string(),
+                    short_channel_id: hop.channel.to_string(),
+                    fee_msat,
+                    cltv_expiry_delta,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(hops)
+    }
+
+    async fn get_channel_info(&self, channel_id: &str) -> Result<ChannelInfo> {
+        let channels = self
+            .list_channels(ListChannelsFilter {
+                scid: Some(channel_id.to_string()),
+                ..Default::default()
+            })
+            .await?;
+
+        // Gossip has one entry per direction, both share capacity
+        let channel = channels
+            .channels
+            .into_iter()
+            .next()
+            .ok_or(RegtestError::NotFound)?;
+
+        let capacity_sat = channel.amount_msat.msat() / 1000;
+
+        // Balances are only known for our own channels
+        let local_balance_sat = self
+            .list_peer_channels()
+            .await?
+            .channels
+            .into_iter()
+            .find(|c| c.short_channel_id.as_ref() == Some(&channel.short_channel_id))
+            .and_then(|c| c.to_us_msat)
+            .map(|to_us| to_us.msat() / 1000)
+            .unwrap_or_default();
+
+        Ok(ChannelInfo {
+            short_channel_id: channel.short_channel_id.to_string(),
+            capacity_sat,
+            local_balance_sat,
+            remote_balance_sat: capacity_sat.saturating_sub(local_balance_sat),
+            active: channel.active,
+            private: !channel.public,
+        })
+    }
+
+    async fn get_pending_channels(&self) -> Result<Vec<PendingChannelInfo>> {
+        let tip_height = self.get_info().await?.blockheight;
+        let channels = self.list_peer_channels().await?;
+
+        let pending_channels = channels
+            .channels
+            .into_iter()
+            .filter(|c| {
+                !matches!(
+                    c.state,
+                    Some(ListpeerchannelsChannelsState::CHANNELD_NORMAL)
+                )
+            })
+            .map(|c| {
+                let awaiting_lockin = matches!(
+                    c.state,
+                    Some(ListpeerchannelsChannelsState::CHANNELD_AWAITING_LOCKIN)
+                        | Some(ListpeerchannelsChannelsState::DUALOPEND_AWAITING_LOCKIN)
+                );
+
+                // Funding is confirmed once the channel has a short channel id
+                let confirmations = c
+                    .short_channel_id
+                    .as_ref()
+                    .map_or(0, |scid| tip_height.saturating_sub(scid.block()) + 1);
+                let blocks_until_open = match awaiting_lockin {
+                    true => c
+                        .minimum_depth
+                        .map(|depth| depth.saturating_sub(confirmations)),
+                    false => None,
+                };
+
+                let funding_outpoint = match (&c.funding_txid, c.funding_outnum) {
+                    (Some(txid), Some(outnum)) => format!("{}:{}", txid, outnum),
+                    _ => String::new(),
+                };
+
+                PendingChannelInfo {
+                    channel_id: funding_outpoint,
+                    capacity_sat: c.total_msat.map_or(0, |a| a.msat() / 1000),
+                    local_balance_sat: c.to_us_msat.map_or(0, |a| a.msat() / 1000),
+                    blocks_until_open,
+                }
+            })
+            .collect();
+
+        Ok(pending_channels)
+    }
+
+    /// CLN only supports a node wide cltv delta, set with `--cltv-delta`,
+    /// so `cltv_delta` has to match the current one
+    async fn update_channel_policy(
+        &self,
+        short_channel_id: &str,
+        base_fee_msat: u64,
+        fee_ppm: u64,
+        cltv_delta: u32,
+    ) -> Result<()> {
+        let fee_ppm = u32::try_from(fee_ppm).map_err(anyhow::Error::from)?;
+
+        let our_id = self.get_info().await?.id.to_string();
+        let (_, _, current_cltv_delta) = self.channel_policy(short_channel_id, &our_id).await?;
+        if cltv_delta != current_cltv_delta {
+            bail!(
+                "CLN cannot set cltv delta {} per channel, node uses {}",
+                cltv_delta,
+                current_cltv_delta
+            );
+        }
+
+        let mut cln_client = self.client.lock().await;
+
         let cln_response = cln_client
-            .call(cln_rpc::Request::Invoice(InvoiceRequest {
-                amount_msat,
-                description: "".to_string(),
-                label,
-                expiry: None,
-                fallbacks: None,
-                preimage: None,
-                cltv: None,
-                deschashonly: None,
-                exposeprivatechannels: None,
+            .call(cln_rpc::Request::SetChannel(SetchannelRequest {
+                id: short_channel_id.to_string(),
+                feebase: Some(Amount::from_msat(base_fee_msat)),
+                feeppm: Some(fee_ppm),
+                htlcmin: None,
+                htlcmax: None,
+                enforcedelay: None,
+                ignorefeelimits: None,
             }))
             .await?;
 
         match cln_response {
-            cln_rpc::Response::Invoice(invoice_res) => Ok(invoice_res.bolt11),
+            cln_rpc::Response::SetChannel(_) => Ok(()),
             _ => {
                 bail!("Wrong cln response");
             }
         }
     }
 
-    async fn pay_invoice(&self, bolt11: String) -> Result<String> {
+    async fn rebalance_channel(
+        &self,
+        out_channel: &str,
+        in_channel: &str,
+        amount_msat: u64,
+    ) -> Result<String> {
+        let our_id = self.get_info().await?.id.to_string();
+
+        let peer_channels = self.list_peer_channels().await?.channels;
+        let channel_peer = |scid: &str| {
+            peer_channels
+                .iter()
+                .find(|c| {
+                    c.short_channel_id
+                        .as_ref()
+                        .map(|s| s.to_string())
+                        .as_deref()
+                        == Some(scid)
+                })
+                .and_then(|c| c.peer_id.as_ref().map(|p| p.to_string()))
+                .ok_or(RegtestError::NotFound)
+        };
+
+        let out_peer = channel_peer(out_channel)?;
+        let in_peer = channel_peer(in_channel)?;
+
+        // Hops as (node, channel into node)
+        let mut hops = vec![(out_peer.clone(), out_channel.to_string())];
+        if out_peer != in_peer {
+            hops.extend(
+                self.route_between(&out_peer, &in_peer, amount_msat, &our_id)
+                    .await?,
+            );
+        }
+        hops.push((our_id, in_channel.to_string()));
+
+        // Amounts and delays are built back from the final hop,
+        // the node forwarding into a channel charges its fee on that channel
+        let mut route = Vec::with_capacity(hops.len());
+        let mut amount = amount_msat;
+        let mut delay = REBALANCE_FINAL_CLTV;
+        for (i, (id, channel)) in hops.iter().enumerate().rev() {
+            route.push(SendpayRoute {
+                id: parse_pubkey(id)?,
+                channel: ShortChannelId::from_str(channel)?,
+                delay,
+                amount_msat: Amount::from_msat(amount),
+            });
+
+            if i > 0 {
+                let (base_fee_msat, fee_ppm, cltv_delta) =
+                    self.channel_policy(channel, &hops[i - 1].0).await?;
+                amount += base_fee_msat + amount * fee_ppm / 1_000_000;
+                delay += cltv_delta;
+            }
+        }
+        route.reverse();
+
         let mut cln_client = self.client.lock().await;
 
         let cln_response = cln_client
-            .call(cln_rpc::Request::Pay(PayRequest {
-                bolt11,
-                amount_msat: None,
+            .call(cln_rpc::Request::Invoice(InvoiceRequest {
+                amount_msat: AmountOrAny::Amount(Amount::from_msat(amount_msat)),
+                description: "rebalance".to_string(),
+                label: format!("{}{}", self.label_prefix, uuid::Uuid::new_v4()),
+                expiry: None,
+                fallbacks: None,
+                preimage: None,
+                cltv: Some(REBALANCE_FINAL_CLTV),
+                deschashonly: None,
+                exposeprivatechannels: None,
+            }))
+            .await?;
+
+        let invoice = match cln_response {
+            cln_rpc::Response::Invoice(invoice_res) => invoice_res,
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::SendPay(SendpayRequest {
+                route,
+                payment_hash: invoice.payment_hash,
                 label: None,
-                riskfactor: None,
-                maxfeepercent: None,
-                retry_for: None,
-                maxdelay: None,
-                exemptfee: None,
+                amount_msat: Some(Amount::from_msat(amount_msat)),
+                bolt11: Some(invoice.bolt11),
+                payment_secret: Some(invoice.payment_secret),
+                partid: None,
                 localinvreqid: None,
-                exclude: None,
-                maxfee: None,
-                description: None,
-                partial_msat: None,
+                groupid: None,
             }))
             .await?;
 
-        let response = match cln_response {
-            cln_rpc::Response::Pay(pay_response) => {
-                Ok(hex::encode(pay_response.payment_preimage.to_vec()))
-            }
-            _ => {
-                bail!("CLN returned wrong response kind");
+        if !matches!(cln_response, cln_rpc::Response::SendPay(_)) {
+            bail!("CLN returned wrong response kind");
+        }
+
+        let cln_response = cln_client
+            .call(cln_rpc::Request::WaitSendPay(WaitsendpayRequest {
+                payment_hash: invoice.payment_hash,
+                timeout: Some(60),
+                partid: None,
+                groupid: None,
+            }))
+            .await?;
+
+        match cln_response {
+            cln_rpc::Response::WaitSendPay(pay_response) => {
+                let preimage = pay_response
+                    .payment_preimage
+                    .ok_or(anyhow!("CLN rebalance returned no preimage"))?;
+
+                Ok(hex::encode(preimage.to_vec()))
             }
+            _ => bail!("CLN returned wrong response kind"),
+        }
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: Option<u64>,
+        expiry_secs: Option<u64>,
+    ) -> Result<String> {
+        let builder = InvoiceBuilder {
+            amount_msat,
+            expiry_secs,
+            ..Default::default()
         };
 
-        // match return_error {
-        //     true => {
-        //         bail!("Lighiting error");
-        //     }
-        //     false => response,
-        // }
+        builder.build(self).await
+    }
 
-        response
+    async fn decode_invoice(&self, bolt11: &str) -> Result<DecodedInvoice> {
+        let decoded = self.decode(bolt11).await?;
+
+        if !decoded.valid {
+            bail!("CLN could not decode invoice");
+        }
+
+        Ok(DecodedInvoice {
+            payment_hash: decoded
+                .payment_hash
+                .ok_or(anyhow!("Invoice has no payment hash"))?
+                .to_string(),
+            amount_msat: decoded.amount_msat.map(|a| a.msat()),
+            description: decoded.description,
+            expiry_secs: decoded.expiry.unwrap_or_default(),
+            timestamp: decoded.created_at.unwrap_or_default(),
+            payee_pubkey: decoded.payee.map(|p| p.to_string()),
+        })
+    }
+
+    async fn pay_invoice(&self, bolt11: String, max_fee_msat: Option<u64>) -> Result<String> {
+        self.pay(bolt11, None, max_fee_msat).await
+    }
+
+    async fn pay_invoice_with_amount(
+        &self,
+        bolt11: String,
+        amount_msat: u64,
+        max_fee_msat: Option<u64>,
+    ) -> Result<String> {
+        self.pay(bolt11, Some(amount_msat), max_fee_msat).await
     }
 
-    async fn wait_chain_sync(&self) -> Result<()> {
+    async fn wait_chain_sync(&self, wait_config: Option<WaitConfig>) -> Result<()> {
+        let wait_config = wait_config.unwrap_or_default();
+
         let mut count = 0;
-        while count < 100 {
+        while count < wait_config.max_attempts {
             let info = self.get_info().await?;
 
             if info.warning_lightningd_sync.is_none() || info.warning_bitcoind_sync.is_none() {
@@ -349,15 +1854,17 @@ impl LightningClient for ClnClient {
             }
             count += 1;
 
-            sleep(Duration::from_secs(2)).await;
+            sleep(wait_config.poll_interval).await;
         }
 
-        bail!("Timeout waiting for pending")
+        Err(RegtestError::Timeout)
     }
 
-    async fn wait_channels_active(&self) -> Result<()> {
+    async fn wait_channels_active(&self, wait_config: Option<WaitConfig>) -> Result<()> {
+        let wait_config = wait_config.unwrap_or_default();
+
         let mut count = 0;
-        while count < 100 {
+        while count < wait_config.max_attempts {
             let mut cln_client = self.client.lock().await;
             let cln_response = cln_client
                 .call(cln_rpc::Request::ListChannels(ListchannelsRequest {
@@ -382,7 +1889,7 @@ impl LightningClient for ClnClient {
 
                     count += 1;
 
-                    sleep(Duration::from_secs(2)).await;
+                    sleep(wait_config.poll_interval).await;
                 }
 
                 _ => {
@@ -391,7 +1898,224 @@ impl LightningClient for ClnClient {
             };
         }
 
-        bail!("Time out exceeded wait for cln channels")
+        Err(RegtestError::Timeout)
+    }
+
+    async fn list_invoices(
+        &self,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<Vec<InvoiceRecord>> {
+        // Paging needs an index, created index starts at 1
+        let paged = limit.is_some() || offset.is_some();
+        let index = paged.then_some(ListinvoicesIndex::CREATED);
+        let start = paged.then(|| offset.unwrap_or_default() as u64 + 1);
+
+        let cln_response = self
+            .client
+            .lock()
+            .await
+            .call(cln_rpc::Request::ListInvoices(ListinvoicesRequest {
+                payment_hash: None,
+                label: None,
+                invstring: None,
+                offer_id: None,
+                index,
+                limit,
+                start,
+            }))
+            .await?;
+
+        let invoices = match cln_response {
+            cln_rpc::Response::ListInvoices(invoice_response) => invoice_response.invoices,
+            _ => bail!("Wrong cln response"),
+        };
+
+        let mut records = Vec::with_capacity(invoices.len());
+
+        for invoice in invoices {
+            // listinvoices has no creation time, it is read from the invoice
+            let created_at = match invoice.bolt11.as_ref().or(invoice.bolt12.as_ref()) {
+                Some(invstring) => self.decode(invstring).await?.created_at.unwrap_or_default(),
+                None => 0,
+            };
+
+            records.push(InvoiceRecord {
+                payment_hash: invoice.payment_hash.to_string(),
+                amount_msat: invoice.amount_msat.map(|a| a.msat()),
+                status: match invoice.status {
+                    ListinvoicesInvoicesStatus::UNPAID => InvoiceStatus::Unpaid,
+                    ListinvoicesInvoicesStatus::EXPIRED => InvoiceStatus::Expired,
+                    ListinvoicesInvoicesStatus::PAID => InvoiceStatus::Paid,
+                },
+                label: Some(invoice.label),
+                created_at,
+            });
+        }
+
+        Ok(records)
+    }
+
+    async fn get_forwarding_history(
+        &self,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<Vec<ForwardingEvent>> {
+        let cln_response = self
+            .client
+            .lock()
+            .await
+            .call(cln_rpc::Request::ListForwards(ListforwardsRequest {
+                status: Some(ListforwardsStatus::SETTLED),
+                in_channel: None,
+                out_channel: None,
+                index: None,
+                start: None,
+                limit: None,
+            }))
+            .await?;
+
+        let forwards = match cln_response {
+            cln_rpc::Response::ListForwards(forwards_response) => forwards_response.forwards,
+            _ => bail!("Wrong cln response"),
+        };
+
+        // listforwards cannot filter by time
+        let events = forwards
+            .into_iter()
+            .map(|forward| ForwardingEvent {
+                chan_id_in: forward.in_channel.to_string(),
+                chan_id_out: forward
+                    .out_channel
+                    .map(|c| c.to_string())
+                    .unwrap_or_default(),
+                amt_in_msat: forward.in_msat.msat(),
+                amt_out_msat: forward.out_msat.map_or(0, |a| a.msat()),
+                fee_msat: forward.fee_msat.map_or(0, |a| a.msat()),
+                timestamp: forward.resolved_time.unwrap_or(forward.received_time) as u64,
+            })
+            .filter(|event| {
+                !start_time.is_some_and(|start| event.timestamp < start)
+                    && !end_time.is_some_and(|end| event.timestamp >= end)
+            })
+            .collect();
+
+        Ok(events)
+    }
+
+    async fn get_network_graph(&self) -> Result<NetworkGraph> {
+        let cln_response = self
+            .client
+            .lock()
+            .await
+            .call(cln_rpc::Request::ListNodes(ListnodesRequest { id: None }))
+            .await?;
+
+        let nodes = match cln_response {
+            cln_rpc::Response::ListNodes(nodes_response) => nodes_response
+                .nodes
+                .into_iter()
+                .map(|node| NodeInfo {
+                    pubkey: node.nodeid.to_string(),
+                    alias: node.alias.unwrap_or_default(),
+                })
+                .collect(),
+            _ => bail!("CLN returned wrong response kind"),
+        };
+
+        let half_channels = self
+            .list_channels(ListChannelsFilter::default())
+            .await?
+            .channels;
+
+        // listchannels returns one entry per direction
+        let mut edges: HashMap<String, ChannelEdge> = HashMap::new();
+
+        for half in half_channels {
+            let source = half.source.to_string();
+            let destination = half.destination.to_string();
+            let source_is_node1 = source < destination;
+
+            let edge = edges
+                .entry(half.short_channel_id.to_string())
+                .or_insert_with(|| {
+                    let (node1_pubkey, node2_pubkey) = match source_is_node1 {
+                        true => (source.clone(), destination.clone()),
+                        false => (destination.clone(), source.clone()),
+                    };
+
+                    ChannelEdge {
+                        channel_id: half.short_channel_id.to_string(),
+                        node1_pubkey,
+                        node2_pubkey,
+                        capacity_sat: half.amount_msat.msat() / 1000,
+                        ..Default::default()
+                    }
+                });
+
+            let policy = ChannelPolicy {
+                base_fee_msat: half.base_fee_millisatoshi as u64,
+                fee_rate_ppm: half.fee_per_millionth as u64,
+                cltv_delta: half.delay,
+                min_htlc_msat: half.htlc_minimum_msat.msat(),
+                disabled: !half.active,
+            };
+
+            match source_is_node1 {
+                true => edge.node1_policy = Some(policy),
+                false => edge.node2_policy = Some(policy),
+            }
+        }
+
+        Ok(NetworkGraph {
+            nodes,
+            channels: edges.into_values().collect(),
+        })
+    }
+
+    async fn list_payments(&self, include_incomplete: bool) -> Result<Vec<PaymentRecord>> {
+        let status = match include_incomplete {
+            true => None,
+            false => Some(ListpaysStatus::COMPLETE),
+        };
+
+        let cln_response = self
+            .client
+            .lock()
+            .await
+            .call(cln_rpc::Request::ListPays(ListpaysRequest {
+                bolt11: None,
+                payment_hash: None,
+                status,
+            }))
+            .await?;
+
+        let pays = match cln_response {
+            cln_rpc::Response::ListPays(pay_response) => pay_response.pays,
+            _ => bail!("Wrong cln response"),
+        };
+
+        let payments = pays
+            .into_iter()
+            .map(|pay| {
+                let amount_msat = pay.amount_msat.map_or(0, |a| a.msat());
+                let amount_sent_msat = pay.amount_sent_msat.map_or(0, |a| a.msat());
+
+                PaymentRecord {
+                    payment_hash: pay.payment_hash.to_string(),
+                    payment_preimage: pay.preimage.map(|p| hex::encode(p.to_vec())),
+                    amount_sent_msat,
+                    fee_msat: amount_sent_msat.saturating_sub(amount_msat),
+                    status: match pay.status {
+                        ListpaysPaysStatus::COMPLETE => InvoiceStatus::Paid,
+                        ListpaysPaysStatus::PENDING => InvoiceStatus::Pending,
+                        ListpaysPaysStatus::FAILED => InvoiceStatus::Failed,
+                    },
+                }
+            })
+            .collect();
+
+        Ok(payments)
     }
 
     async fn check_incoming_payment_status(&self, payment_hash: &str) -> Result<InvoiceStatus> {
@@ -417,9 +2141,7 @@ impl LightningClient for ClnClient {
                         ListinvoicesInvoicesStatus::EXPIRED => Ok(InvoiceStatus::Expired),
                         ListinvoicesInvoicesStatus::PAID => Ok(InvoiceStatus::Paid),
                     },
-                    None => {
-                        bail!("Could not find invoice")
-                    }
+                    None => Err(RegtestError::NotFound),
                 }
             }
             _ => {
@@ -433,7 +2155,7 @@ impl LightningClient for ClnClient {
         let cln_response = cln_client
             .call(cln_rpc::Request::ListPays(ListpaysRequest {
                 bolt11: None,
-                payment_hash: Some(payment_hash.parse()?),
+                payment_hash: Some(payment_hash.parse().map_err(anyhow::Error::from)?),
                 status: None,
             }))
             .await?;
@@ -459,3 +2181,23 @@ impl LightningClient for ClnClient {
         Ok(state)
     }
 }
+
+/// First CLN version with a stable `renepay`
+pub const MIN_RENEPAY_VERSION: (u32, u32, u32) = (23, 11, 0);
+
+/// First CLN version with `listclosedchannels`
+pub const MIN_LISTCLOSEDCHANNELS_VERSION: (u32, u32, u32) = (23, 5, 0);
+
+fn short_id(pubkey: &str) -> String {
+    pubkey.chars().take(8).collect()
+}
+
+fn parse_pubkey(pubkey: &str) -> Result<PublicKey> {
+    Ok(PublicKey::from_str(pubkey).map_err(anyhow::Error::from)?)
+}
+
+fn is_peer_disconnected(err: &str) -> bool {
+    let err = err.to_lowercase();
+
+    err.contains("not connected") || err.contains("disconnected") || err.contains("unknown peer")
+}
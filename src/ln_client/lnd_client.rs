@@ -1,25 +1,126 @@
 //! LND Client
 
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::anyhow;
 use async_trait::async_trait;
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
 use fedimint_tonic_lnd::{
+    invoicesrpc::{
+        AddHoldInvoiceRequest, CancelInvoiceMsg, SettleInvoiceMsg, SubscribeSingleInvoiceRequest,
+    },
     lnrpc::{
-        ConnectPeerRequest, GetInfoRequest, GetInfoResponse, LightningAddress, ListChannelsRequest,
-        NewAddressRequest, OpenChannelRequest, WalletBalanceRequest,
+        channel_point::FundingTxid, failure::FailureCode, fee_limit, htlc_attempt::HtlcStatus,
+        payment::PaymentStatus, policy_update_request, restore_chan_backup_request, AddressType,
+        ChanBackupExportRequest, ChanInfoRequest, ChannelAcceptResponse, ChannelGraphRequest,
+        ChannelPoint, CloseChannelRequest, CommitmentType, ConnectPeerRequest,
+        DisconnectPeerRequest, FeeLimit, ForwardingHistoryRequest, GetInfoRequest, GetInfoResponse,
+        GetTransactionsRequest, Hop, InvoiceSubscription, LightningAddress, ListChannelsRequest,
+        ListInvoiceRequest, ListPeersRequest, ListUnspentRequest, MppRecord, NewAddressRequest,
+        OpenChannelRequest, PayReqString, PeerEvent, PeerEventSubscription, PendingChannelsRequest,
+        PolicyUpdateRequest, QueryRoutesRequest, RestoreChanBackupRequest, Route, SendCoinsRequest,
+        SendCustomMessageRequest, SendManyRequest, WalletBalanceRequest,
+    },
+    routerrpc::{
+        htlc_event, GetMissionControlConfigRequest, MissionControlConfig, SendPaymentRequest,
+        SendToRouteRequest, SetMissionControlConfigRequest, SubscribeHtlcEventsRequest,
+    },
+    walletrpc::{
+        import_tapscript_request, ImportTapscriptRequest, ListAccountsRequest, TapLeaf,
+        TapscriptFullTree,
     },
     Client,
 };
-use tokio::{sync::Mutex, time::sleep};
+use futures_util::{future, stream, Stream, StreamExt};
+use tokio::{
+    sync::{mpsc, oneshot, Mutex},
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
 
-use crate::{hex, InvoiceStatus};
+use crate::{bail, hex, InvoiceStatus, RegtestError, Result};
 
 use super::{
-    types::{Balance, ConnectInfo},
-    LightningClient,
+    parse_version,
+    types::{
+        AddressType as OnchainAddressType, Balance, ChannelAcceptorPolicy, ChannelEdge,
+        ChannelInfo, ChannelOpenDecision, ChannelOpenRequest, ChannelPolicy, ChannelType,
+        ConnectInfo, DecodedInvoice, ForwardingEvent, HtlcEvent, HtlcEventType, InvoiceRecord,
+        InvoiceUpdate, NetworkGraph, NodeHealth, NodeInfo, OnChainTx, PaymentRecord, PeerInfo,
+        PendingChannelInfo, RouteHint, RouteHop, Utxo, WaitConfig, WatchOnlyAccount,
+    },
+    LightningClient, REBALANCE_FINAL_CLTV,
 };
 
+/// Invoice with optional metadata, created with [`InvoiceBuilder::build`]
+#[derive(Debug, Clone, Default)]
+pub struct InvoiceBuilder {
+    amount_msat: Option<u64>,
+    description: Option<String>,
+    expiry_secs: Option<u64>,
+    preimage: Option<String>,
+}
+
+impl InvoiceBuilder {
+    /// Create new [`InvoiceBuilder`] for an amountless invoice
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Invoice amount
+    pub fn amount_msat(mut self, amount_msat: u64) -> Self {
+        self.amount_msat = Some(amount_msat);
+        self
+    }
+
+    /// Invoice description
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Seconds until the invoice expires
+    pub fn expiry_secs(mut self, expiry_secs: u64) -> Self {
+        self.expiry_secs = Some(expiry_secs);
+        self
+    }
+
+    /// Hex encoded preimage, LND generates one if not set
+    pub fn preimage(mut self, preimage: &str) -> Self {
+        self.preimage = Some(preimage.to_string());
+        self
+    }
+
+    /// Create invoice on node
+    ///
+    /// Returns the bolt11
+    pub async fn build(&self, client: &LndClient) -> Result<String> {
+        let r_preimage = match &self.preimage {
+            Some(preimage) => hex::decode(preimage)?,
+            None => vec![],
+        };
+
+        let invoice_request = fedimint_tonic_lnd::lnrpc::Invoice {
+            value_msat: self.amount_msat.unwrap_or_default() as i64,
+            memo: self.description.clone().unwrap_or_default(),
+            expiry: self.expiry_secs.unwrap_or_default() as i64,
+            r_preimage,
+            ..Default::default()
+        };
+
+        let invoice = client
+            .client
+            .lock()
+            .await
+            .lightning()
+            .add_invoice(invoice_request)
+            .await?
+            .into_inner();
+
+        Ok(invoice.payment_request)
+    }
+}
+
 /// Lnd
 pub struct LndClient {
     pub address: String,
@@ -108,293 +209,2053 @@ impl LndClient {
 
         Ok(balance as u64)
     }
-}
-
-#[async_trait]
-impl LightningClient for LndClient {
-    async fn get_connect_info(&self) -> Result<ConnectInfo> {
-        let info = self.get_info().await?;
-        let uri = info.uris.first().unwrap();
 
-        let parsed = parse_uri(&uri);
+    /// Get wallet account by name
+    pub async fn get_watch_only_account(&self, account: &str) -> Result<WatchOnlyAccount> {
+        let accounts = self
+            .client
+            .lock()
+            .await
+            .wallet()
+            .list_accounts(ListAccountsRequest {
+                name: account.to_string(),
+                address_type: 0,
+            })
+            .await?
+            .into_inner();
 
-        Ok(parsed.unwrap())
+        let account = accounts
+            .accounts
+            .into_iter()
+            .find(|a| a.name == account)
+            .ok_or(anyhow!("Unknown account"))?;
+
+        Ok(WatchOnlyAccount {
+            name: account.name,
+            address_type: account.address_type as u32,
+            extended_pubkey: account.extended_public_key,
+            master_key_fingerprint: hex::encode(account.master_key_fingerprint),
+        })
     }
 
-    async fn get_new_onchain_address(&self) -> Result<String> {
-        let client = &self.client;
+    /// Import tapscript as watch only
+    ///
+    /// The script is the only leaf under an unspendable internal key.
+    /// Returns the p2tr address of the imported script
+    pub async fn import_tapscript_address(&self, tapscript_hex: &str) -> Result<String> {
+        let leaf = TapLeaf {
+            leaf_version: TAPSCRIPT_LEAF_VERSION,
+            script: hex::decode(tapscript_hex)?,
+        };
 
-        let new_address_request = NewAddressRequest {
-            r#type: 0,
-            account: "".to_string(),
+        let request = ImportTapscriptRequest {
+            internal_public_key: hex::decode(UNSPENDABLE_INTERNAL_KEY)?,
+            script: Some(import_tapscript_request::Script::FullTree(
+                TapscriptFullTree {
+                    all_leaves: vec![leaf],
+                },
+            )),
         };
 
-        let new_address_response = client
+        let response = self
+            .client
             .lock()
             .await
-            .lightning()
-            .new_address(new_address_request)
+            .wallet()
+            .import_tapscript(request)
             .await?
             .into_inner();
 
-        Ok(new_address_response.address.to_string())
+        Ok(response.p2tr_address)
     }
 
-    async fn connect_peer(&self, pubkey: String, addr: String, port: u16) -> Result<()> {
-        let client = &self.client;
+    /// Get LND version as (major, minor, patch)
+    pub async fn get_version(&self) -> Result<(u32, u32, u32)> {
+        let info = self.get_info().await?;
 
-        let host = format!("{}:{}", addr, port);
+        parse_version(&info.version)
+    }
 
-        let lightning_addr = LightningAddress { pubkey, host };
+    /// Error if LND version is older than the minimum
+    pub async fn assert_min_version(&self, major: u32, minor: u32, patch: u32) -> Result<()> {
+        let version = self.get_version().await?;
+
+        if version < (major, minor, patch) {
+            bail!(
+                "LND version {}.{}.{} is older than required {}.{}.{}",
+                version.0,
+                version.1,
+                version.2,
+                major,
+                minor,
+                patch
+            );
+        }
 
-        let connect_peer_request = ConnectPeerRequest {
-            addr: Some(lightning_addr),
-            perm: false,
-            timeout: 60,
-        };
+        Ok(())
+    }
 
-        let _connect_peer = client
+    /// Get `pubkey@host:port` uris other nodes can connect to
+    pub async fn get_bootstrap_peers(&self) -> Result<Vec<String>> {
+        let info = self.get_info().await?;
+
+        Ok(info.uris)
+    }
+
+    /// Get feature bits advertised by a connected peer
+    ///
+    /// Maps each feature bit to `known` or `unknown`
+    pub async fn get_peer_features(&self, pubkey: &str) -> Result<HashMap<u32, String>> {
+        let peers = self
+            .client
             .lock()
             .await
             .lightning()
-            .connect_peer(connect_peer_request)
+            .list_peers(ListPeersRequest {
+                latest_error: false,
+            })
             .await?
             .into_inner();
 
-        tracing::info!("LND connected to peer");
+        let peer = peers
+            .peers
+            .into_iter()
+            .find(|p| p.pub_key == pubkey)
+            .ok_or(RegtestError::NotFound)?;
+
+        let features = peer
+            .features
+            .into_iter()
+            .map(|(bit, feature)| {
+                let known = match feature.is_known {
+                    true => "known",
+                    false => "unknown",
+                };
+
+                (bit, known.to_string())
+            })
+            .collect();
 
-        Ok(())
+        Ok(features)
     }
 
-    async fn open_channel(
-        &self,
-        amount_sat: u64,
-        peer_id: &str,
-        push_amount: Option<u64>,
-    ) -> Result<()> {
-        let client = &self.client;
+    /// Get address types supported by the node
+    ///
+    /// Taproot is only reported when the node advertises taproot channel support
+    pub async fn get_supported_address_types(&self) -> Result<Vec<AddressType>> {
+        let info = self.get_info().await?;
 
-        let mut open_channel_request = OpenChannelRequest::default();
+        let mut address_types = vec![
+            AddressType::WitnessPubkeyHash,
+            AddressType::NestedPubkeyHash,
+        ];
 
-        open_channel_request.node_pubkey = hex::decode(peer_id)?;
-        open_channel_request.push_sat = push_amount.unwrap_or_default() as i64;
-        open_channel_request.local_funding_amount = amount_sat as i64;
+        // Simple taproot channels staging feature bits
+        if info.features.contains_key(&180) || info.features.contains_key(&181) {
+            address_types.push(AddressType::TaprootPubkey);
+        }
 
-        let _connect_peer = client
+        Ok(address_types)
+    }
+
+    /// Get new onchain address of type
+    pub async fn get_new_address_of_type(&self, t: AddressType) -> Result<String> {
+        let new_address_request = NewAddressRequest {
+            r#type: t as i32,
+            account: "".to_string(),
+        };
+
+        let new_address_response = self
+            .client
             .lock()
             .await
             .lightning()
-            .open_channel_sync(open_channel_request)
+            .new_address(new_address_request)
             .await?
             .into_inner();
 
-        tracing::info!("LND opened channel");
-
-        Ok(())
+        Ok(new_address_response.address)
     }
 
-    async fn balance(&self) -> Result<Balance> {
-        let client = &self.client;
+    /// Get failure reason of outgoing payment
+    ///
+    /// Returns `None` if the payment has not failed
+    pub async fn get_payment_failure_reason(&self, payment_hash: &str) -> Result<Option<String>> {
+        let payments_request = fedimint_tonic_lnd::lnrpc::ListPaymentsRequest {
+            include_incomplete: true,
+            index_offset: 0,
+            max_payments: 1000,
+            reversed: false,
+            count_total_payments: false,
+        };
 
-        let response = client
+        let payments = self
+            .client
             .lock()
             .await
             .lightning()
-            .wallet_balance(WalletBalanceRequest {})
+            .list_payments(payments_request)
             .await?
             .into_inner();
 
-        let ln = self.channels_balance().await?;
+        let payment = payments
+            .payments
+            .iter()
+            .find(|p| p.payment_hash == payment_hash)
+            .ok_or(RegtestError::NotFound)?;
+
+        let reason = match payment.failure_reason {
+            0 => None,
+            1 => Some("FailureReasonTimeout"),
+            2 => Some("FailureReasonNoRoute"),
+            3 => Some("FailureReasonError"),
+            4 => Some("FailureReasonIncorrectPaymentDetails"),
+            5 => Some("FailureReasonInsufficientBalance"),
+            _ => bail!("Unknown failure reason"),
+        };
 
-        Ok(Balance {
-            on_chain_spendable: response.confirmed_balance as u64,
-            on_chain_total: response.total_balance as u64,
-            ln,
-        })
+        Ok(reason.map(|r| r.to_string()))
     }
 
-    async fn pay_invoice(&self, bolt11: String) -> Result<String> {
-        let pay_req = fedimint_tonic_lnd::lnrpc::SendRequest {
-            payment_request: bolt11,
+    /// Send onchain to many addresses in one transaction
+    ///
+    /// Returns the txid
+    pub async fn send_to_many(
+        &self,
+        outputs: HashMap<String, u64>,
+        fee_rate_sat_per_vb: u64,
+    ) -> Result<String> {
+        let send_many_request = SendManyRequest {
+            addr_to_amount: outputs
+                .into_iter()
+                .map(|(addr, amount)| (addr, amount as i64))
+                .collect(),
+            sat_per_vbyte: fee_rate_sat_per_vb,
             ..Default::default()
         };
 
-        let payment_response = self
+        let response = self
             .client
             .lock()
             .await
             .lightning()
-            .send_payment_sync(fedimint_tonic_lnd::tonic::Request::new(pay_req))
+            .send_many(send_many_request)
             .await?
             .into_inner();
 
-        Ok(hex::encode(payment_response.payment_preimage))
+        Ok(response.txid)
     }
 
-    async fn create_invoice(&self, amount_sat: Option<u64>) -> Result<String> {
-        let value_msat = amount_sat.map(|a| (a * 1_000) as i64).unwrap_or(0);
-
-        let invoice_request = fedimint_tonic_lnd::lnrpc::Invoice {
-            value_msat,
-            ..Default::default()
-        };
-
-        let invoice = self
+    /// Build pubkey to alias map of nodes in the graph
+    pub async fn build_alias_map(&self) -> Result<HashMap<String, String>> {
+        let graph = self
             .client
             .lock()
             .await
             .lightning()
-            .add_invoice(fedimint_tonic_lnd::tonic::Request::new(invoice_request))
-            .await
-            .unwrap()
+            .describe_graph(ChannelGraphRequest {
+                include_unannounced: true,
+            })
+            .await?
             .into_inner();
 
-        Ok(invoice.payment_request)
-    }
+        let alias_map = graph
+            .nodes
+            .into_iter()
+            .map(|node| (node.pub_key, node.alias))
+            .collect();
 
-    async fn wait_channels_active(&self) -> Result<()> {
-        let mut count = 0;
-        while count < 100 {
-            let pending = self
-                .client
-                .lock()
-                .await
-                .lightning()
-                .list_channels(ListChannelsRequest {
-                    inactive_only: true,
-                    active_only: false,
-                    public_only: false,
-                    private_only: false,
-                    peer: vec![],
-                })
-                .await?
-                .into_inner();
+        Ok(alias_map)
+    }
 
-            if pending.channels.is_empty() {
-                tracing::info!("All LND channels active");
-                return Ok(());
+    /// Forward peer events to channel
+    ///
+    /// Resubscribes with exponential backoff, capped at 10 seconds, when the stream ends.
+    /// The task exits once the receiver is dropped.
+    pub async fn resilient_peer_events(&self, event_tx: mpsc::Sender<PeerEvent>) -> JoinHandle<()> {
+        let client = Arc::clone(&self.client);
+
+        tokio::spawn(async move {
+            let max_backoff = Duration::from_secs(10);
+            let mut backoff = Duration::from_millis(250);
+
+            loop {
+                let stream = client
+                    .lock()
+                    .await
+                    .lightning()
+                    .subscribe_peer_events(PeerEventSubscription {})
+                    .await;
+
+                match stream {
+                    Ok(stream) => {
+                        let mut stream = stream.into_inner();
+
+                        while let Some(event) = stream.next().await {
+                            match event {
+                                Ok(event) => {
+                                    backoff = Duration::from_millis(250);
+
+                                    if event_tx.send(event).await.is_err() {
+                                        return;
+                                    }
+                                }
+                                Err(err) => {
+                                    tracing::warn!("LND peer event stream error: {}", err);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!("Could not subscribe to LND peer events: {}", err);
+                    }
+                }
+
+                if event_tx.is_closed() {
+                    return;
+                }
+
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
             }
+        })
+    }
 
-            count += 1;
+    /// Start channel acceptor deciding on incoming channel opens with policy
+    ///
+    /// Returns once the acceptor stream is open. Runs until the stream ends, while it runs
+    /// LND does not accept channels on its own
+    pub async fn start_channel_acceptor(
+        &self,
+        policy: ChannelAcceptorPolicy,
+    ) -> Result<JoinHandle<()>> {
+        // LND sends nothing on the stream until a channel is opened, so the call
+        // does not hold the client lock
+        let mut lightning = self.client.lock().await.lightning().clone();
+        let (active_tx, active_rx) = oneshot::channel();
+
+        let acceptor = tokio::spawn(async move {
+            let (response_tx, response_rx) = mpsc::channel(16);
+
+            // The response stream is first polled once the rpc has been sent to LND
+            let responses = stream::unfold(
+                (response_rx, Some(active_tx)),
+                |(mut response_rx, mut active_tx)| async move {
+                    if let Some(active_tx) = active_tx.take() {
+                        active_tx.send(()).ok();
+                    }
+
+                    response_rx
+                        .recv()
+                        .await
+                        .map(|response| (response, (response_rx, active_tx)))
+                },
+            );
+
+            let requests = lightning.channel_acceptor(responses).await;
+
+            let mut requests = match requests {
+                Ok(requests) => requests.into_inner(),
+                Err(err) => {
+                    tracing::warn!("Could not start LND channel acceptor: {}", err);
+                    return;
+                }
+            };
+
+            while let Some(request) = requests.next().await {
+                let request = match request {
+                    Ok(request) => request,
+                    Err(err) => {
+                        tracing::warn!("LND channel acceptor stream error: {}", err);
+                        return;
+                    }
+                };
+
+                let decision = policy
+                    .decide(ChannelOpenRequest {
+                        node_pubkey: hex::encode(&request.node_pubkey),
+                        pending_chan_id: hex::encode(&request.pending_chan_id),
+                        funding_amt_sat: request.funding_amt,
+                        push_amt_msat: request.push_amt,
+                    })
+                    .await;
+
+                let response = match decision {
+                    ChannelOpenDecision::Accept => ChannelAcceptResponse {
+                        accept: true,
+                        pending_chan_id: request.pending_chan_id,
+                        ..Default::default()
+                    },
+                    ChannelOpenDecision::Reject(error) => ChannelAcceptResponse {
+                        accept: false,
+                        pending_chan_id: request.pending_chan_id,
+                        error,
+                        ..Default::default()
+                    },
+                };
+
+                if response_tx.send(response).await.is_err() {
+                    return;
+                }
+            }
+        });
 
-            sleep(Duration::from_secs(2)).await;
-        }
+        active_rx
+            .await
+            .map_err(|_| anyhow!("LND channel acceptor stopped before it was active"))?;
 
-        bail!("Timeout waiting for pending")
+        Ok(acceptor)
     }
 
-    async fn wait_chain_sync(&self) -> Result<()> {
-        let mut count = 0;
-        while count < 100 {
-            let info = self.get_info().await?;
+    /// Send raw peer message
+    ///
+    /// Message types below 32768 must be allowed with `--protocol.custom-message`
+    pub async fn dev_send_raw_message(
+        &self,
+        peer_pubkey: &str,
+        msg_type: u32,
+        msg_hex: &str,
+    ) -> Result<()> {
+        let request = SendCustomMessageRequest {
+            peer: hex::decode(peer_pubkey)?,
+            r#type: msg_type,
+            data: hex::decode(msg_hex)?,
+        };
 
-            if info.synced_to_chain {
-                tracing::info!("LND completed chain sync");
-                return Ok(());
-            }
-            count += 1;
+        self.client
+            .lock()
+            .await
+            .lightning()
+            .send_custom_message(request)
+            .await?;
 
-            sleep(Duration::from_secs(2)).await;
-        }
+        tracing::debug!("LND sent message of type {} to {}", msg_type, peer_pubkey);
 
-        bail!("Time out exceeded")
+        Ok(())
     }
 
-    async fn check_incoming_payment_status(&self, payment_hash: &str) -> Result<InvoiceStatus> {
-        let invoice_request = fedimint_tonic_lnd::lnrpc::PaymentHash {
-            r_hash: hex::decode(payment_hash)?,
-            ..Default::default()
-        };
-
-        let invoice = self
+    /// Export static channel backup of all channels
+    pub async fn export_channel_backup(&self) -> Result<Vec<u8>> {
+        let snapshot = self
             .client
             .lock()
             .await
             .lightning()
-            .lookup_invoice(fedimint_tonic_lnd::tonic::Request::new(invoice_request))
-            .await
-            .unwrap()
+            .export_all_channel_backups(ChanBackupExportRequest {})
+            .await?
             .into_inner();
 
-        match invoice.state {
-            // Open
-            0 => Ok(InvoiceStatus::Unpaid),
-            // Settled
-            1 => Ok(InvoiceStatus::Paid),
-            // Canceled
-            2 => Ok(InvoiceStatus::Unpaid),
-            // Accepted
-            3 => Ok(InvoiceStatus::Unpaid),
-            _ => bail!("Unknown state"),
-        }
+        let backup = snapshot
+            .multi_chan_backup
+            .ok_or(anyhow!("LND returned no multi channel backup"))?;
+
+        Ok(backup.multi_chan_backup)
     }
 
-    async fn check_outgoing_payment_status(&self, payment_hash: &str) -> Result<InvoiceStatus> {
-        let invoice_request = fedimint_tonic_lnd::lnrpc::ListPaymentsRequest {
-            include_incomplete: true,
-            index_offset: 0,
-            max_payments: 1000,
-            reversed: false,
-            count_total_payments: false,
+    /// Restore channels from a multi channel backup, closing them with the peers
+    ///
+    /// Channels that are still in the channel database are skipped
+    pub async fn restore_channel_backup(&self, backup: &[u8]) -> Result<()> {
+        let request = RestoreChanBackupRequest {
+            backup: Some(restore_chan_backup_request::Backup::MultiChanBackup(
+                backup.to_vec(),
+            )),
         };
 
-        let invoices = self
-            .client
+        self.client
             .lock()
             .await
             .lightning()
-            .list_payments(invoice_request)
-            .await
-            .unwrap()
-            .into_inner();
+            .restore_channel_backups(request)
+            .await?;
 
-        let invoice: Vec<&fedimint_tonic_lnd::lnrpc::Payment> = invoices
-            .payments
-            .iter()
-            .filter(|p| p.payment_hash == payment_hash)
-            .collect();
+        Ok(())
+    }
 
-        if invoice.len() != 1 {
-            bail!("Could not find invoice");
-        }
+    /// Export multi channel backup once it contains at least one channel
+    pub async fn export_channel_backup_ready(&self, timeout: Duration) -> Result<Vec<u8>> {
+        let start = Instant::now();
 
-        let invoice = invoice.first().expect("Checked len is one");
+        while start.elapsed() < timeout {
+            let snapshot = self
+                .client
+                .lock()
+                .await
+                .lightning()
+                .export_all_channel_backups(ChanBackupExportRequest {})
+                .await?
+                .into_inner();
+
+            let channel_count = snapshot
+                .single_chan_backups
+                .map(|b| b.chan_backups.len())
+                .unwrap_or_default();
 
-        match invoice.status {
-            // Open
-            0 => Ok(InvoiceStatus::Unpaid),
-            // Settled
-            1 => Ok(InvoiceStatus::Paid),
-            // Canceled
-            2 => Ok(InvoiceStatus::Unpaid),
-            // Accepted
-            3 => Ok(InvoiceStatus::Unpaid),
-            _ => bail!("Unknown state"),
+            if channel_count > 0 {
+                let backup = snapshot
+                    .multi_chan_backup
+                    .ok_or(anyhow!("LND returned no multi channel backup"))?;
+
+                return Ok(backup.multi_chan_backup);
+            }
+
+            sleep(Duration::from_secs(1)).await;
         }
+
+        Err(RegtestError::Timeout)
     }
-}
 
-fn parse_uri(uri: &str) -> Option<ConnectInfo> {
-    // Split at the '@' symbol to separate the node_id and the rest (addr and port)
-    let parts: Vec<&str> = uri.split('@').collect();
+    /// Get mission control config
+    pub async fn get_mc_config(&self) -> Result<MissionControlConfig> {
+        let response = self
+            .client
+            .lock()
+            .await
+            .router()
+            .get_mission_control_config(GetMissionControlConfigRequest {})
+            .await?
+            .into_inner();
 
-    if parts.len() != 2 {
-        return None; // If the format is invalid
+        response
+            .config
+            .ok_or(anyhow!("LND returned no mission control config"))
     }
 
-    let node_id = parts[0].to_string();
-    let address_parts: Vec<&str> = parts[1].split(':').collect();
+    /// Update mission control config
+    pub async fn update_mc_config(&self, config: MissionControlConfig) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .router()
+            .set_mission_control_config(SetMissionControlConfigRequest {
+                config: Some(config),
+            })
+            .await?;
 
-    if address_parts.len() != 2 {
-        return None; // If the address and port format is invalid
+        Ok(())
     }
 
-    let addr = address_parts[0].to_string();
-    let port: u16 = address_parts[1].parse().ok()?; // Parse the port as u16
+    /// Stop channel from routing payments
+    ///
+    /// LND treats a max htlc of zero as unchanged so it is lowered to the channel min htlc
+    pub async fn disable_channel_for_routing(&self, chan_id: u64) -> Result<()> {
+        self.update_channel_max_htlc(chan_id, |constraints| constraints.min_htlc_msat)
+            .await
+    }
 
-    Some(ConnectInfo {
-        pubkey: node_id,
-        address: addr,
-        port,
-    })
+    /// Allow channel to route payments up to the max pending amount
+    pub async fn enable_channel_for_routing(&self, chan_id: u64) -> Result<()> {
+        self.update_channel_max_htlc(chan_id, |constraints| constraints.max_pending_amt_msat)
+            .await
+    }
+
+    async fn update_channel_max_htlc(
+        &self,
+        chan_id: u64,
+        max_htlc_msat: impl Fn(&fedimint_tonic_lnd::lnrpc::ChannelConstraints) -> u64,
+    ) -> Result<()> {
+        let channels = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: false,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await?
+            .into_inner();
+
+        let channel = channels
+            .channels
+            .into_iter()
+            .find(|c| c.chan_id == chan_id)
+            .ok_or(RegtestError::NotFound)?;
+
+        let constraints = channel
+            .local_constraints
+            .ok_or(anyhow!("LND returned no channel constraints"))?;
+
+        let our_pubkey = self.get_info().await?.identity_pubkey;
+
+        let edge = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .get_chan_info(ChanInfoRequest {
+                chan_id,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        // Keep the current fee policy and only change max htlc
+        let policy = match edge.node1_pub == our_pubkey {
+            true => edge.node1_policy,
+            false => edge.node2_policy,
+        }
+        .ok_or(anyhow!("LND returned no channel policy"))?;
+
+        let (funding_txid, output_index) = channel
+            .channel_point
+            .split_once(':')
+            .ok_or(anyhow!("Invalid channel point"))?;
+
+        let policy_update_request = PolicyUpdateRequest {
+            scope: Some(policy_update_request::Scope::ChanPoint(ChannelPoint {
+                funding_txid: Some(FundingTxid::FundingTxidStr(funding_txid.to_string())),
+                output_index: output_index.parse()?,
+            })),
+            base_fee_msat: policy.fee_base_msat,
+            fee_rate_ppm: policy.fee_rate_milli_msat as u32,
+            time_lock_delta: policy.time_lock_delta,
+            max_htlc_msat: max_htlc_msat(&constraints),
+            ..Default::default()
+        };
+
+        self.client
+            .lock()
+            .await
+            .lightning()
+            .update_channel_policy(policy_update_request)
+            .await?;
+
+        tracing::info!(
+            "LND set channel {} max htlc to {} msat",
+            chan_id,
+            max_htlc_msat(&constraints)
+        );
+
+        Ok(())
+    }
+
+    /// Create hold invoice for payment hash
+    pub async fn create_hold_invoice(
+        &self,
+        amount_msat: u64,
+        payment_hash: &[u8],
+    ) -> Result<String> {
+        let hold_invoice_request = AddHoldInvoiceRequest {
+            hash: payment_hash.to_vec(),
+            value_msat: amount_msat as i64,
+            ..Default::default()
+        };
+
+        let hold_invoice = self
+            .client
+            .lock()
+            .await
+            .invoices()
+            .add_hold_invoice(hold_invoice_request)
+            .await?
+            .into_inner();
+
+        Ok(hold_invoice.payment_request)
+    }
+
+    /// Pay own invoice over a circular route out through `out_channel` and back in
+    /// through `in_channel`
+    ///
+    /// Returns the preimage. Hold invoices keep this pending until settled or cancelled
+    pub async fn pay_self_via_channels(
+        &self,
+        out_channel: &str,
+        in_channel: &str,
+        bolt11: &str,
+    ) -> Result<String> {
+        let out_chan_id = cln_scid_to_lnd_u64(out_channel)?;
+        let in_chan_id = cln_scid_to_lnd_u64(in_channel)?;
+
+        let pay_req = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .decode_pay_req(PayReqString {
+                pay_req: bolt11.to_string(),
+            })
+            .await?
+            .into_inner();
+        let amount_msat = pay_req.num_msat as u64;
+
+        let info = self.get_info().await?;
+        let our_pubkey = info.identity_pubkey;
+
+        let channels = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: false,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await?
+            .into_inner();
+
+        let channel_peer = |chan_id: u64| {
+            channels
+                .channels
+                .iter()
+                .find(|c| c.chan_id == chan_id)
+                .map(|c| c.remote_pubkey.clone())
+                .ok_or(RegtestError::NotFound)
+        };
+
+        let out_peer = channel_peer(out_chan_id)?;
+        let in_peer = channel_peer(in_chan_id)?;
+
+        // Hops as (node, channel into node)
+        let mut hops = vec![(out_peer.clone(), out_chan_id)];
+        if out_peer != in_peer {
+            let routes = self
+                .client
+                .lock()
+                .await
+                .lightning()
+                .query_routes(QueryRoutesRequest {
+                    pub_key: in_peer.clone(),
+                    amt_msat: amount_msat as i64,
+                    source_pub_key: out_peer.clone(),
+                    ignored_nodes: vec![hex::decode(&our_pubkey)?],
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+
+            let route = routes.routes.into_iter().next().ok_or(anyhow!(
+                "No route from {} to {}",
+                out_peer,
+                in_peer
+            ))?;
+
+            hops.extend(route.hops.into_iter().map(|hop| (hop.pub_key, hop.chan_id)));
+        }
+        hops.push((our_pubkey, in_chan_id));
+
+        // Amounts and expiries are built back from the final hop,
+        // the node forwarding into a channel charges its fee on that channel
+        let mut route_hops = Vec::with_capacity(hops.len());
+        let mut amount = amount_msat;
+        let mut fee_msat = 0;
+        let mut expiry = info.block_height + pay_req.cltv_expiry as u32;
+        let mut cltv_delta = 0;
+        for (i, (pubkey, chan_id)) in hops.iter().enumerate().rev() {
+            route_hops.push(Hop {
+                chan_id: *chan_id,
+                pub_key: pubkey.clone(),
+                amt_to_forward_msat: amount as i64,
+                fee_msat: fee_msat as i64,
+                expiry,
+                tlv_payload: true,
+                ..Default::default()
+            });
+
+            amount += fee_msat;
+            expiry += cltv_delta;
+
+            if i > 0 {
+                let policy = self.channel_policy(*chan_id, &hops[i - 1].0).await?;
+                fee_msat = policy.base_fee_msat + amount * policy.fee_rate_ppm / 1_000_000;
+                cltv_delta = policy.cltv_delta;
+            }
+        }
+        route_hops.reverse();
+
+        if let Some(final_hop) = route_hops.last_mut() {
+            final_hop.mpp_record = Some(MppRecord {
+                payment_addr: pay_req.payment_addr,
+                total_amt_msat: amount_msat as i64,
+            });
+        }
+
+        let route = Route {
+            total_time_lock: expiry,
+            total_fees_msat: (amount - amount_msat) as i64,
+            total_amt_msat: amount as i64,
+            hops: route_hops,
+            ..Default::default()
+        };
+
+        let attempt = self
+            .client
+            .lock()
+            .await
+            .router()
+            .send_to_route_v2(SendToRouteRequest {
+                payment_hash: hex::decode(&pay_req.payment_hash)?,
+                route: Some(route),
+                skip_temp_err: false,
+            })
+            .await?
+            .into_inner();
+
+        match HtlcStatus::try_from(attempt.status) {
+            Ok(HtlcStatus::Succeeded) => Ok(hex::encode(attempt.preimage)),
+            _ => {
+                let code = attempt
+                    .failure
+                    .and_then(|failure| FailureCode::try_from(failure.code).ok());
+                bail!("LND circular payment failed: {:?}", code)
+            }
+        }
+    }
+
+    /// Settle accepted hold invoice
+    pub async fn settle_hold_invoice(&self, preimage: &[u8]) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .invoices()
+            .settle_invoice(SettleInvoiceMsg {
+                preimage: preimage.to_vec(),
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Cancel hold invoice
+    pub async fn cancel_hold_invoice(&self, payment_hash: &[u8]) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .invoices()
+            .cancel_invoice(CancelInvoiceMsg {
+                payment_hash: payment_hash.to_vec(),
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Subscribe to state updates of a single invoice
+    pub async fn subscribe_invoice_updates(
+        &self,
+        payment_hash: &str,
+    ) -> Result<impl Stream<Item = Result<InvoiceStatus>>> {
+        let request = SubscribeSingleInvoiceRequest {
+            r_hash: hex::decode(payment_hash)?,
+        };
+
+        let stream = self
+            .client
+            .lock()
+            .await
+            .invoices()
+            .subscribe_single_invoice(request)
+            .await?
+            .into_inner();
+
+        Ok(stream.map(|invoice| invoice_state_to_status(invoice?.state)))
+    }
+
+    /// Forwarding policy of channel in direction from source
+    async fn channel_policy(&self, chan_id: u64, source: &str) -> Result<ChannelPolicy> {
+        let edge = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .get_chan_info(ChanInfoRequest {
+                chan_id,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        let policy = match edge.node1_pub == source {
+            true => edge.node1_policy,
+            false => edge.node2_policy,
+        }
+        .ok_or(anyhow!("LND returned no channel policy"))?;
+
+        Ok(channel_policy_from_lnd(policy))
+    }
+
+    /// Get channel info by `block:tx:index` or `BLOCKxTXxOUTPUT` short channel id
+    pub async fn get_channel_info_by_scid_string(&self, scid: &str) -> Result<ChannelInfo> {
+        self.get_channel_info(scid).await
+    }
+
+    /// Pay bolt11 invoice, amount is required for invoices without one
+    async fn pay(
+        &self,
+        bolt11: String,
+        amount_msat: Option<u64>,
+        max_fee_msat: Option<u64>,
+    ) -> Result<String> {
+        let pay_req = fedimint_tonic_lnd::lnrpc::SendRequest {
+            payment_request: bolt11,
+            amt_msat: amount_msat.map_or(0, |a| a as i64),
+            fee_limit: max_fee_msat.map(|max_fee| FeeLimit {
+                limit: Some(fee_limit::Limit::FixedMsat(max_fee as i64)),
+            }),
+            ..Default::default()
+        };
+
+        let payment_response = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .send_payment_sync(fedimint_tonic_lnd::tonic::Request::new(pay_req))
+            .await?
+            .into_inner();
+
+        // Failed payments are reported in the response, not as an rpc error
+        if !payment_response.payment_error.is_empty() {
+            bail!("LND payment failed: {}", payment_response.payment_error);
+        }
+
+        if payment_response.payment_preimage.is_empty() {
+            bail!("LND payment returned no preimage");
+        }
+
+        Ok(hex::encode(payment_response.payment_preimage))
+    }
+
+    /// Pay bolt11 invoice with route hints in addition to those in the invoice
+    ///
+    /// Returns the payment preimage
+    pub async fn pay_invoice_with_additional_hints(
+        &self,
+        bolt11: String,
+        route_hints: Vec<RouteHint>,
+    ) -> Result<String> {
+        let route_hints = route_hints
+            .into_iter()
+            .map(|hint| {
+                let hop_hints = hint
+                    .hop_hints
+                    .into_iter()
+                    .map(|hop| fedimint_tonic_lnd::lnrpc::HopHint {
+                        node_id: hop.node_id,
+                        chan_id: hop.chan_id,
+                        fee_base_msat: hop.fee_base_msat,
+                        fee_proportional_millionths: hop.fee_proportional_millionths,
+                        cltv_expiry_delta: hop.cltv_expiry_delta,
+                    })
+                    .collect();
+
+                fedimint_tonic_lnd::lnrpc::RouteHint { hop_hints }
+            })
+            .collect();
+
+        // Regtest payments are not fee sensitive
+        let send_payment_request = SendPaymentRequest {
+            payment_request: bolt11,
+            route_hints,
+            timeout_seconds: 60,
+            fee_limit_sat: 10_000,
+            no_inflight_updates: true,
+            ..Default::default()
+        };
+
+        let mut payment_stream = self
+            .client
+            .lock()
+            .await
+            .router()
+            .send_payment_v2(send_payment_request)
+            .await?
+            .into_inner();
+
+        while let Some(payment) = payment_stream.message().await? {
+            match PaymentStatus::try_from(payment.status) {
+                Ok(PaymentStatus::Succeeded) => return Ok(payment.payment_preimage),
+                Ok(PaymentStatus::Failed) => {
+                    bail!("LND payment failed: {:?}", payment.failure_reason())
+                }
+                _ => (),
+            }
+        }
+
+        bail!("LND payment stream ended before payment completed")
+    }
+
+    /// Subscribe to updates of all invoices
+    pub async fn subscribe_invoices(&self) -> Result<impl Stream<Item = Result<InvoiceUpdate>>> {
+        let stream = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .subscribe_invoices(InvoiceSubscription::default())
+            .await?
+            .into_inner();
+
+        Ok(stream.map(|invoice| {
+            let invoice = invoice?;
+
+            Ok(InvoiceUpdate {
+                payment_hash: hex::encode(invoice.r_hash),
+                status: invoice_state_to_status(invoice.state)?,
+                amount_paid_msat: invoice.amt_paid_msat as u64,
+            })
+        }))
+    }
+
+    /// Subscribe to forward, settle and failure events of all htlcs
+    pub async fn subscribe_htlc_events(&self) -> Result<impl Stream<Item = Result<HtlcEvent>>> {
+        let stream = self
+            .client
+            .lock()
+            .await
+            .router()
+            .subscribe_htlc_events(SubscribeHtlcEventsRequest {})
+            .await?
+            .into_inner();
+
+        // Subscription confirmations and final htlc events are skipped
+        Ok(stream.filter_map(|event| {
+            let event = match event {
+                Ok(event) => htlc_event_from_lnd(event).map(Ok),
+                Err(err) => Some(Err(err.into())),
+            };
+
+            future::ready(event)
+        }))
+    }
+}
+
+#[async_trait]
+impl LightningClient for LndClient {
+    async fn get_connect_info(&self) -> Result<ConnectInfo> {
+        let info = self.get_info().await?;
+        let uri = info.uris.first().unwrap();
+
+        let mut parsed = parse_uri(&uri).unwrap();
+        parsed.alias = Some(info.alias).filter(|alias| !alias.is_empty());
+
+        Ok(parsed)
+    }
+
+    async fn get_new_onchain_address(
+        &self,
+        addr_type: Option<OnchainAddressType>,
+    ) -> Result<String> {
+        let address_type = match addr_type {
+            None | Some(OnchainAddressType::P2wpkh) => AddressType::WitnessPubkeyHash,
+            Some(OnchainAddressType::P2shP2wpkh) => AddressType::NestedPubkeyHash,
+            Some(OnchainAddressType::P2tr) => AddressType::TaprootPubkey,
+        };
+
+        self.get_new_address_of_type(address_type).await
+    }
+
+    async fn send_on_chain(&self, address: &str, amount_sat: u64) -> Result<String> {
+        let send_coins_request = SendCoinsRequest {
+            addr: address.to_string(),
+            amount: amount_sat as i64,
+            ..Default::default()
+        };
+
+        let send_coins_response = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .send_coins(send_coins_request)
+            .await?
+            .into_inner();
+
+        Ok(send_coins_response.txid)
+    }
+
+    async fn list_onchain_transactions(&self) -> Result<Vec<OnChainTx>> {
+        // End height of -1 includes unconfirmed transactions
+        let transactions = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .get_transactions(GetTransactionsRequest {
+                start_height: 0,
+                end_height: -1,
+                account: "".to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(transactions
+            .transactions
+            .into_iter()
+            .map(|tx| OnChainTx {
+                txid: tx.tx_hash,
+                block_height: (tx.block_height > 0).then_some(tx.block_height as u32),
+                amount_msat: tx.amount * 1000,
+                confirms: tx.num_confirmations.max(0) as u32,
+            })
+            .collect())
+    }
+
+    async fn list_utxos(&self) -> Result<Vec<Utxo>> {
+        // Min confs of 0 includes unconfirmed outputs
+        let unspent = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_unspent(ListUnspentRequest {
+                min_confs: 0,
+                max_confs: i32::MAX,
+                account: "".to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(unspent
+            .utxos
+            .into_iter()
+            .map(|utxo| {
+                let (txid, vout) = utxo
+                    .outpoint
+                    .map(|outpoint| (outpoint.txid_str, outpoint.output_index))
+                    .unwrap_or_default();
+
+                Utxo {
+                    txid,
+                    vout,
+                    amount_sat: utxo.amount_sat.max(0) as u64,
+                    address: utxo.address,
+                    confirmations: utxo.confirmations.max(0) as u32,
+                    spendable: utxo.confirmations > 0,
+                }
+            })
+            .collect())
+    }
+
+    async fn connect_peer(&self, pubkey: String, addr: String, port: u16) -> Result<()> {
+        let client = &self.client;
+
+        let host = format!("{}:{}", addr, port);
+
+        let lightning_addr = LightningAddress { pubkey, host };
+
+        let connect_peer_request = ConnectPeerRequest {
+            addr: Some(lightning_addr),
+            perm: false,
+            timeout: 60,
+        };
+
+        let _connect_peer = client
+            .lock()
+            .await
+            .lightning()
+            .connect_peer(connect_peer_request)
+            .await
+            .map_err(
+                |status| match status.message().contains("already connected") {
+                    true => RegtestError::AlreadyExists,
+                    false => status.into(),
+                },
+            )?
+            .into_inner();
+
+        tracing::info!("LND connected to peer");
+
+        Ok(())
+    }
+
+    async fn disconnect_peer(&self, pubkey: &str) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .lightning()
+            .disconnect_peer(DisconnectPeerRequest {
+                pub_key: pubkey.to_string(),
+            })
+            .await?;
+
+        tracing::info!("LND disconnected from peer");
+
+        Ok(())
+    }
+
+    async fn list_peers(&self) -> Result<Vec<PeerInfo>> {
+        let peers = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_peers(ListPeersRequest {
+                latest_error: false,
+            })
+            .await?
+            .into_inner();
+
+        let channels = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: false,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await?
+            .into_inner();
+
+        // LND only lists connected peers
+        let peers = peers
+            .peers
+            .into_iter()
+            .map(|peer| PeerInfo {
+                channels: channels
+                    .channels
+                    .iter()
+                    .filter(|c| c.remote_pubkey == peer.pub_key)
+                    .count() as u32,
+                pubkey: peer.pub_key,
+                connected: true,
+            })
+            .collect();
+
+        Ok(peers)
+    }
+
+    async fn open_channel(
+        &self,
+        amount_sat: u64,
+        peer_id: &str,
+        push_amount: Option<u64>,
+        announce: bool,
+        channel_type: Option<ChannelType>,
+        minconf: Option<u32>,
+    ) -> Result<()> {
+        let client = &self.client;
+
+        let commitment_type = match channel_type {
+            None => CommitmentType::UnknownCommitmentType,
+            Some(ChannelType::LegacyStaticKey) => CommitmentType::StaticRemoteKey,
+            Some(ChannelType::AnchorZeroFeeHtlcTx) => CommitmentType::Anchors,
+            Some(ChannelType::AnchorOutputs) => {
+                bail!("LND only supports zero fee htlc anchor channels")
+            }
+        };
+
+        let mut open_channel_request = OpenChannelRequest::default();
+
+        open_channel_request.commitment_type = commitment_type as i32;
+        open_channel_request.private = !announce;
+
+        if let Some(minconf) = minconf {
+            open_channel_request.min_confs = minconf as i32;
+            open_channel_request.spend_unconfirmed = minconf == 0;
+        }
+
+        open_channel_request.node_pubkey = hex::decode(peer_id)?;
+        open_channel_request.push_sat = push_amount.unwrap_or_default() as i64;
+        open_channel_request.local_funding_amount = amount_sat as i64;
+
+        let _connect_peer = client
+            .lock()
+            .await
+            .lightning()
+            .open_channel_sync(open_channel_request)
+            .await?
+            .into_inner();
+
+        tracing::info!("LND opened channel");
+
+        Ok(())
+    }
+
+    /// `channel_id` may be the short channel id or the `txid:index` channel point
+    async fn close_channel(&self, channel_id: &str, force: bool) -> Result<()> {
+        let channels = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: false,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await?
+            .into_inner();
+
+        let chan_id = cln_scid_to_lnd_u64(channel_id).ok();
+
+        let channel = channels
+            .channels
+            .iter()
+            .find(|c| Some(c.chan_id) == chan_id || c.channel_point == channel_id)
+            .ok_or(RegtestError::NotFound)?;
+
+        let (funding_txid, output_index) = channel
+            .channel_point
+            .split_once(':')
+            .ok_or(anyhow!("Invalid channel point"))?;
+
+        let close_channel_request = CloseChannelRequest {
+            channel_point: Some(ChannelPoint {
+                funding_txid: Some(FundingTxid::FundingTxidStr(funding_txid.to_string())),
+                output_index: output_index.parse()?,
+            }),
+            force,
+            ..Default::default()
+        };
+
+        let mut close_stream = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .close_channel(close_channel_request)
+            .await?
+            .into_inner();
+
+        // First update is sent once the closing tx is broadcast
+        close_stream
+            .message()
+            .await?
+            .ok_or(anyhow!("LND close channel stream ended"))?;
+
+        tracing::info!("LND closed channel {}", channel_id);
+
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<NodeHealth> {
+        let info = self.get_info().await?;
+        let balance = self.balance().await?;
+
+        Ok(NodeHealth {
+            chain_synced: info.synced_to_chain,
+            graph_synced: info.synced_to_graph,
+            active_channels: info.num_active_channels,
+            pending_channels: info.num_pending_channels,
+            on_chain_balance_sat: balance.on_chain_total / 1000,
+            ln_balance_msat: balance.ln,
+        })
+    }
+
+    async fn balance(&self) -> Result<Balance> {
+        let client = &self.client;
+
+        let response = client
+            .lock()
+            .await
+            .lightning()
+            .wallet_balance(WalletBalanceRequest {})
+            .await?
+            .into_inner();
+
+        let ln = self.channels_balance().await?;
+
+        // LND reports sat, balance is in msat like CLN
+        Ok(Balance {
+            on_chain_spendable: response.confirmed_balance as u64 * 1000,
+            on_chain_total: response.total_balance as u64 * 1000,
+            ln: ln * 1000,
+        })
+    }
+
+    async fn pay_invoice(&self, bolt11: String, max_fee_msat: Option<u64>) -> Result<String> {
+        self.pay(bolt11, None, max_fee_msat).await
+    }
+
+    async fn pay_invoice_with_amount(
+        &self,
+        bolt11: String,
+        amount_msat: u64,
+        max_fee_msat: Option<u64>,
+    ) -> Result<String> {
+        self.pay(bolt11, Some(amount_msat), max_fee_msat).await
+    }
+
+    async fn keysend(&self, node_pubkey: &str, amount_msat: u64) -> Result<String> {
+        // Keysend preimage is chosen by the sender and sent in a custom record
+        let preimage = [
+            uuid::Uuid::new_v4().into_bytes(),
+            uuid::Uuid::new_v4().into_bytes(),
+        ]
+        .concat();
+        let payment_hash = sha256::Hash::hash(&preimage);
+
+        let send_payment_request = SendPaymentRequest {
+            dest: hex::decode(node_pubkey)?,
+            amt_msat: amount_msat as i64,
+            payment_hash: payment_hash.to_byte_array().to_vec(),
+            dest_custom_records: HashMap::from([(KEYSEND_RECORD_TYPE, preimage.clone())]),
+            timeout_seconds: 60,
+            fee_limit_msat: (amount_msat / 100) as i64 + 1_000,
+            no_inflight_updates: true,
+            ..Default::default()
+        };
+
+        let mut payment_stream = self
+            .client
+            .lock()
+            .await
+            .router()
+            .send_payment_v2(send_payment_request)
+            .await?
+            .into_inner();
+
+        while let Some(payment) = payment_stream.message().await? {
+            match PaymentStatus::try_from(payment.status) {
+                Ok(PaymentStatus::Succeeded) => return Ok(hex::encode(preimage)),
+                Ok(PaymentStatus::Failed) => {
+                    bail!("LND keysend failed: {:?}", payment.failure_reason())
+                }
+                _ => (),
+            }
+        }
+
+        bail!("LND keysend stream ended before payment completed")
+    }
+
+    async fn get_route(&self, destination: &str, amount_msat: u64) -> Result<Vec<RouteHop>> {
+        let block_height = self.get_info().await?.block_height;
+
+        let routes = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .query_routes(QueryRoutesRequest {
+                pub_key: destination.to_string(),
+                amt_msat: amount_msat as i64,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        let route = routes
+            .routes
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("No route to {}", destination))?;
+
+        // Hop expiries are absolute heights
+        let hops = route
+            .hops
+            .iter()
+            .enumerate()
+            .map(|(i, hop)| {
+                let next_expiry = route
+                    .hops
+                    .get(i + 1)
+                    .map(|next| next.expiry)
+                    .unwrap_or(block_height);
+
+                let cltv_expiry_delta = hop
+                    .expiry
+                    .checked_sub(next_expiry)
+                    .ok_or(anyhow!("LND route expiry increases after hop {}", i))?;
+
+                Ok(RouteHop {
+                    pubkey: hop.pub_key.clone(),
+                    short_channel_id: format_short_channel_id(hop.chan_id),
+                    fee_msat: hop.fee_msat as u64,
+                    cltv_expiry_delta,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(hops)
+    }
+
+    async fn get_channel_info(&self, channel_id: &str) -> Result<ChannelInfo> {
+        let chan_id = cln_scid_to_lnd_u64(channel_id)?;
+
+        let edge = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .get_chan_info(ChanInfoRequest {
+                chan_id,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        let channels = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: false,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await?
+            .into_inner();
+
+        // Balances are only known for our own channels
+        let channel = channels.channels.into_iter().find(|c| c.chan_id == chan_id);
+
+        Ok(ChannelInfo {
+            short_channel_id: format_short_channel_id(edge.channel_id),
+            capacity_sat: edge.capacity as u64,
+            local_balance_sat: channel.as_ref().map_or(0, |c| c.local_balance as u64),
+            remote_balance_sat: channel.as_ref().map_or(0, |c| c.remote_balance as u64),
+            active: channel.as_ref().is_some_and(|c| c.active),
+            private: channel.is_some_and(|c| c.private),
+        })
+    }
+
+    async fn get_pending_channels(&self) -> Result<Vec<PendingChannelInfo>> {
+        let pending = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .pending_channels(PendingChannelsRequest::default())
+            .await?
+            .into_inner();
+
+        // LND does not report confirmations left for opening channels
+        let opening = pending.pending_open_channels.into_iter().map(|c| c.channel);
+        let closing = pending
+            .waiting_close_channels
+            .into_iter()
+            .map(|c| c.channel)
+            .chain(
+                pending
+                    .pending_force_closing_channels
+                    .into_iter()
+                    .map(|c| c.channel),
+            );
+
+        Ok(opening
+            .chain(closing)
+            .flatten()
+            .map(|c| PendingChannelInfo {
+                channel_id: c.channel_point,
+                capacity_sat: c.capacity as u64,
+                local_balance_sat: c.local_balance as u64,
+                blocks_until_open: None,
+            })
+            .collect())
+    }
+
+    async fn update_channel_policy(
+        &self,
+        short_channel_id: &str,
+        base_fee_msat: u64,
+        fee_ppm: u64,
+        cltv_delta: u32,
+    ) -> Result<()> {
+        let chan_id = cln_scid_to_lnd_u64(short_channel_id)?;
+
+        let channels = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: false,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await?
+            .into_inner();
+
+        let channel = channels
+            .channels
+            .into_iter()
+            .find(|c| c.chan_id == chan_id)
+            .ok_or(RegtestError::NotFound)?;
+
+        let (funding_txid, output_index) = channel
+            .channel_point
+            .split_once(':')
+            .ok_or(anyhow!("Invalid channel point"))?;
+
+        // Max htlc of zero is left unchanged by LND
+        let policy_update_request = PolicyUpdateRequest {
+            scope: Some(policy_update_request::Scope::ChanPoint(ChannelPoint {
+                funding_txid: Some(FundingTxid::FundingTxidStr(funding_txid.to_string())),
+                output_index: output_index.parse()?,
+            })),
+            base_fee_msat: base_fee_msat as i64,
+            fee_rate_ppm: u32::try_from(fee_ppm).map_err(anyhow::Error::from)?,
+            time_lock_delta: cltv_delta,
+            ..Default::default()
+        };
+
+        self.client
+            .lock()
+            .await
+            .lightning()
+            .update_channel_policy(policy_update_request)
+            .await?;
+
+        tracing::info!("LND updated channel {} policy", chan_id);
+
+        Ok(())
+    }
+
+    async fn rebalance_channel(
+        &self,
+        out_channel: &str,
+        in_channel: &str,
+        amount_msat: u64,
+    ) -> Result<String> {
+        let invoice = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .add_invoice(fedimint_tonic_lnd::lnrpc::Invoice {
+                value_msat: amount_msat as i64,
+                cltv_expiry: REBALANCE_FINAL_CLTV as u64,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        self.pay_self_via_channels(out_channel, in_channel, &invoice.payment_request)
+            .await
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: Option<u64>,
+        expiry_secs: Option<u64>,
+    ) -> Result<String> {
+        let builder = InvoiceBuilder {
+            amount_msat,
+            expiry_secs,
+            ..Default::default()
+        };
+
+        builder.build(self).await
+    }
+
+    async fn decode_invoice(&self, bolt11: &str) -> Result<DecodedInvoice> {
+        let pay_req = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .decode_pay_req(PayReqString {
+                pay_req: bolt11.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(DecodedInvoice {
+            payment_hash: pay_req.payment_hash,
+            amount_msat: (pay_req.num_msat > 0).then_some(pay_req.num_msat as u64),
+            description: (!pay_req.description.is_empty()).then_some(pay_req.description),
+            expiry_secs: pay_req.expiry as u64,
+            timestamp: pay_req.timestamp as u64,
+            payee_pubkey: Some(pay_req.destination),
+        })
+    }
+
+    async fn wait_channels_active(&self, wait_config: Option<WaitConfig>) -> Result<()> {
+        let wait_config = wait_config.unwrap_or_default();
+
+        let mut count = 0;
+        while count < wait_config.max_attempts {
+            let pending = self
+                .client
+                .lock()
+                .await
+                .lightning()
+                .list_channels(ListChannelsRequest {
+                    inactive_only: true,
+                    active_only: false,
+                    public_only: false,
+                    private_only: false,
+                    peer: vec![],
+                })
+                .await?
+                .into_inner();
+
+            if pending.channels.is_empty() {
+                tracing::info!("All LND channels active");
+                return Ok(());
+            }
+
+            count += 1;
+
+            sleep(wait_config.poll_interval).await;
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    async fn wait_chain_sync(&self, wait_config: Option<WaitConfig>) -> Result<()> {
+        let wait_config = wait_config.unwrap_or_default();
+
+        let mut count = 0;
+        while count < wait_config.max_attempts {
+            let info = self.get_info().await?;
+
+            if info.synced_to_chain {
+                tracing::info!("LND completed chain sync");
+                return Ok(());
+            }
+            count += 1;
+
+            sleep(wait_config.poll_interval).await;
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    async fn list_invoices(
+        &self,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<Vec<InvoiceRecord>> {
+        let list_request = ListInvoiceRequest {
+            index_offset: offset.unwrap_or_default() as u64,
+            num_max_invoices: limit.map_or(u64::MAX, u64::from),
+            ..Default::default()
+        };
+
+        let invoices = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_invoices(list_request)
+            .await?
+            .into_inner();
+
+        invoices
+            .invoices
+            .into_iter()
+            .map(|invoice| {
+                Ok(InvoiceRecord {
+                    payment_hash: hex::encode(invoice.r_hash),
+                    amount_msat: (invoice.value_msat > 0).then_some(invoice.value_msat as u64),
+                    status: invoice_state_to_status(invoice.state)?,
+                    label: None,
+                    created_at: invoice.creation_date as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn get_forwarding_history(
+        &self,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<Vec<ForwardingEvent>> {
+        // LND treats an end time of zero as now
+        let history_request = ForwardingHistoryRequest {
+            start_time: start_time.unwrap_or_default(),
+            end_time: end_time.unwrap_or_default(),
+            num_max_events: u32::MAX,
+            ..Default::default()
+        };
+
+        let history = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .forwarding_history(history_request)
+            .await?
+            .into_inner();
+
+        Ok(history
+            .forwarding_events
+            .into_iter()
+            .map(|event| ForwardingEvent {
+                chan_id_in: format_short_channel_id(event.chan_id_in),
+                chan_id_out: format_short_channel_id(event.chan_id_out),
+                amt_in_msat: event.amt_in_msat,
+                amt_out_msat: event.amt_out_msat,
+                fee_msat: event.fee_msat,
+                timestamp: event.timestamp_ns / 1_000_000_000,
+            })
+            .collect())
+    }
+
+    async fn get_network_graph(&self) -> Result<NetworkGraph> {
+        let graph = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .describe_graph(ChannelGraphRequest {
+                include_unannounced: true,
+            })
+            .await?
+            .into_inner();
+
+        let nodes = graph
+            .nodes
+            .into_iter()
+            .map(|node| NodeInfo {
+                pubkey: node.pub_key,
+                alias: node.alias,
+            })
+            .collect();
+
+        let channels = graph
+            .edges
+            .into_iter()
+            .map(|edge| ChannelEdge {
+                channel_id: format_short_channel_id(edge.channel_id),
+                node1_pubkey: edge.node1_pub,
+                node2_pubkey: edge.node2_pub,
+                capacity_sat: edge.capacity as u64,
+                node1_policy: edge.node1_policy.map(channel_policy_from_lnd),
+                node2_policy: edge.node2_policy.map(channel_policy_from_lnd),
+            })
+            .collect();
+
+        Ok(NetworkGraph { nodes, channels })
+    }
+
+    async fn list_payments(&self, include_incomplete: bool) -> Result<Vec<PaymentRecord>> {
+        let payments_request = fedimint_tonic_lnd::lnrpc::ListPaymentsRequest {
+            include_incomplete,
+            max_payments: u64::MAX,
+            ..Default::default()
+        };
+
+        let payments = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_payments(payments_request)
+            .await?
+            .into_inner();
+
+        payments
+            .payments
+            .into_iter()
+            .map(|payment| {
+                // Preimage is all zeros until the payment succeeds
+                let payment_preimage = (!payment.payment_preimage.chars().all(|c| c == '0'))
+                    .then_some(payment.payment_preimage);
+
+                Ok(PaymentRecord {
+                    payment_hash: payment.payment_hash,
+                    payment_preimage,
+                    amount_sent_msat: (payment.value_msat + payment.fee_msat) as u64,
+                    fee_msat: payment.fee_msat as u64,
+                    status: payment_status_to_status(payment.status)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn check_incoming_payment_status(&self, payment_hash: &str) -> Result<InvoiceStatus> {
+        let invoice_request = fedimint_tonic_lnd::lnrpc::PaymentHash {
+            r_hash: hex::decode(payment_hash)?,
+            ..Default::default()
+        };
+
+        let invoice = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .lookup_invoice(fedimint_tonic_lnd::tonic::Request::new(invoice_request))
+            .await?
+            .into_inner();
+
+        invoice_state_to_status(invoice.state)
+    }
+
+    async fn check_outgoing_payment_status(&self, payment_hash: &str) -> Result<InvoiceStatus> {
+        let invoice_request = fedimint_tonic_lnd::lnrpc::ListPaymentsRequest {
+            include_incomplete: true,
+            index_offset: 0,
+            max_payments: 1000,
+            reversed: false,
+            count_total_payments: false,
+        };
+
+        let invoices = self
+            .client
+            .lock()
+            .await
+            .lightning()
+            .list_payments(invoice_request)
+            .await?
+            .into_inner();
+
+        let invoice: Vec<&fedimint_tonic_lnd::lnrpc::Payment> = invoices
+            .payments
+            .iter()
+            .filter(|p| p.payment_hash == payment_hash)
+            .collect();
+
+        if invoice.len() != 1 {
+            return Err(RegtestError::NotFound);
+        }
+
+        let invoice = invoice.first().expect("Checked len is one");
+
+        payment_status_to_status(invoice.status)
+    }
+}
+
+/// Custom record type carrying the keysend preimage
+const KEYSEND_RECORD_TYPE: u64 = 5482373484;
+
+/// BIP 341 tapscript leaf version
+const TAPSCRIPT_LEAF_VERSION: u32 = 0xc0;
+
+/// BIP 341 NUMS point, x only key without a known private key
+const UNSPENDABLE_INTERNAL_KEY: &str =
+    "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// Readable name for pubkey from alias map
+pub fn resolve_pubkey(pubkey: &str, map: &HashMap<String, String>) -> String {
+    match map.get(pubkey) {
+        Some(alias) if !alias.is_empty() => {
+            format!("{} ({})", alias, pubkey.get(..8).unwrap_or(pubkey))
+        }
+        _ => pubkey.get(..16).unwrap_or(pubkey).to_string(),
+    }
+}
+
+/// Format LND numeric channel id as `blockxtxxoutput`
+fn format_short_channel_id(chan_id: u64) -> String {
+    format!(
+        "{}x{}x{}",
+        chan_id >> 40,
+        (chan_id >> 16) & 0xFFFFFF,
+        chan_id & 0xFFFF
+    )
+}
+
+/// Convert `block:tx:index` or `blockxtxxindex` short channel id to LND u64 encoding
+pub fn cln_scid_to_lnd_u64(scid: &str) -> Result<u64> {
+    let parts: Vec<&str> = scid.split([':', 'x']).collect();
+
+    let (block, tx, index) = match parts.as_slice() {
+        [block, tx, index] => (
+            block.parse::<u64>()?,
+            tx.parse::<u64>()?,
+            index.parse::<u64>()?,
+        ),
+        _ => bail!("Invalid short channel id: {}", scid),
+    };
+
+    if block > 0xFFFFFF || tx > 0xFFFFFF || index > 0xFFFF {
+        bail!("Short channel id out of range: {}", scid);
+    }
+
+    Ok(block << 40 | tx << 16 | index)
+}
+
+fn channel_policy_from_lnd(policy: fedimint_tonic_lnd::lnrpc::RoutingPolicy) -> ChannelPolicy {
+    ChannelPolicy {
+        base_fee_msat: policy.fee_base_msat as u64,
+        fee_rate_ppm: policy.fee_rate_milli_msat as u64,
+        cltv_delta: policy.time_lock_delta,
+        min_htlc_msat: policy.min_htlc as u64,
+        disabled: policy.disabled,
+    }
+}
+
+fn payment_status_to_status(status: i32) -> Result<InvoiceStatus> {
+    match PaymentStatus::try_from(status) {
+        Ok(PaymentStatus::Succeeded) => Ok(InvoiceStatus::Paid),
+        Ok(PaymentStatus::Failed) => Ok(InvoiceStatus::Failed),
+        Ok(_) => Ok(InvoiceStatus::Pending),
+        Err(_) => bail!("Unknown payment status"),
+    }
+}
+
+fn invoice_state_to_status(state: i32) -> Result<InvoiceStatus> {
+    match state {
+        // Open
+        0 => Ok(InvoiceStatus::Unpaid),
+        // Settled
+        1 => Ok(InvoiceStatus::Paid),
+        // Canceled
+        2 => Ok(InvoiceStatus::Unpaid),
+        // Accepted
+        3 => Ok(InvoiceStatus::Accepted),
+        _ => bail!("Unknown state"),
+    }
+}
+
+fn htlc_event_from_lnd(event: fedimint_tonic_lnd::routerrpc::HtlcEvent) -> Option<HtlcEvent> {
+    let (event_type, payment_hash) = match event.event? {
+        htlc_event::Event::ForwardEvent(_) => (HtlcEventType::Forward, None),
+        htlc_event::Event::ForwardFailEvent(_) => (HtlcEventType::ForwardFail, None),
+        htlc_event::Event::SettleEvent(settle) => (
+            HtlcEventType::Settle,
+            Some(sha256::Hash::hash(&settle.preimage).to_string()),
+        ),
+        htlc_event::Event::LinkFailEvent(_) => (HtlcEventType::Link, None),
+        _ => return None,
+    };
+
+    Some(HtlcEvent {
+        incoming_channel_id: event.incoming_channel_id,
+        outgoing_channel_id: event.outgoing_channel_id,
+        event_type,
+        payment_hash,
+    })
+}
+
+fn parse_uri(uri: &str) -> Option<ConnectInfo> {
+    // Split at the '@' symbol to separate the node_id and the rest (addr and port)
+    let parts: Vec<&str> = uri.split('@').collect();
+
+    if parts.len() != 2 {
+        return None; // If the format is invalid
+    }
+
+    let node_id = parts[0].to_string();
+    let address_parts: Vec<&str> = parts[1].split(':').collect();
+
+    if address_parts.len() != 2 {
+        return None; // If the address and port format is invalid
+    }
+
+    let addr = address_parts[0].to_string();
+    let port: u16 = address_parts[1].parse().ok()?; // Parse the port as u16
+
+    Some(ConnectInfo {
+        pubkey: node_id,
+        address: addr,
+        port,
+        alias: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cln_scid_to_lnd_u64() {
+        let chan_id = cln_scid_to_lnd_u64("103:1:0").unwrap();
+        assert_eq!(chan_id, 113249697726464);
+        assert_eq!(format_short_channel_id(chan_id), "103x1x0");
+        assert_eq!(cln_scid_to_lnd_u64("103x1x0").unwrap(), chan_id);
+        assert!(cln_scid_to_lnd_u64("103:1").is_err());
+        assert!(cln_scid_to_lnd_u64("103:1:70000").is_err());
+    }
 }
@@ -1,54 +1,340 @@
-use anyhow::Result;
+use std::time::Duration;
+
 use async_trait::async_trait;
+use tokio::time::{sleep, Instant};
+
+use crate::{bail, InvoiceStatus, RegtestError, Result};
 
-use crate::InvoiceStatus;
+use self::types::{
+    AddressType, Balance, ChannelInfo, ChannelType, ConnectInfo, DecodedInvoice, ForwardingEvent,
+    InvoiceRecord, NetworkGraph, NodeHealth, OnChainTx, PaymentRecord, PeerInfo,
+    PendingChannelInfo, RouteHop, Utxo, WaitConfig,
+};
 
-use self::types::{Balance, ConnectInfo};
+/// Final cltv delta of rebalance invoices
+pub(crate) const REBALANCE_FINAL_CLTV: u32 = 40;
 
+pub mod cln_channel_cache;
 pub mod cln_client;
 pub mod lnd_client;
 pub mod types;
 
+pub use cln_channel_cache::ClnChannelCache;
 pub use cln_client::ClnClient;
 pub use lnd_client::LndClient;
 
+/// Common interface of CLN and LND clients
+///
+/// Channels are identified by their short channel id in `BLOCKxTXxOUTPUT` form,
+/// pending channels without one by their funding outpoint `txid:vout`
 #[async_trait]
 pub trait LightningClient {
     /// Get info required to connect to the node
     async fn get_connect_info(&self) -> Result<ConnectInfo>;
 
+    /// Get node pubkey
+    async fn get_node_pubkey(&self) -> Result<String> {
+        Ok(self.get_connect_info().await?.pubkey)
+    }
+
+    /// Get sync state, channel counts and balances of the node
+    async fn health_check(&self) -> Result<NodeHealth>;
+
     /// Get new onchain address
-    async fn get_new_onchain_address(&self) -> Result<String>;
+    ///
+    /// `None` returns a p2wpkh address
+    async fn get_new_onchain_address(&self, addr_type: Option<AddressType>) -> Result<String>;
+
+    /// Send on chain funds from the node wallet
+    ///
+    /// Returns the txid
+    async fn send_on_chain(&self, address: &str, amount_sat: u64) -> Result<String>;
+
+    /// List on chain wallet transactions
+    async fn list_onchain_transactions(&self) -> Result<Vec<OnChainTx>>;
+
+    /// List unspent outputs of the on chain wallet
+    async fn list_utxos(&self) -> Result<Vec<Utxo>>;
 
     /// Connect to a peer
     async fn connect_peer(&self, pubkey: String, addr: String, port: u16) -> Result<()>;
 
+    /// Disconnect from a peer
+    async fn disconnect_peer(&self, pubkey: &str) -> Result<()>;
+
+    /// Disconnect from peer and connect again after optional `delay`
+    async fn reconnect_peer(
+        &self,
+        pubkey: &str,
+        addr: &str,
+        port: u16,
+        delay: Option<Duration>,
+    ) -> Result<()> {
+        self.disconnect_peer(pubkey).await?;
+
+        if let Some(delay) = delay {
+            sleep(delay).await;
+        }
+
+        self.connect_peer(pubkey.to_string(), addr.to_string(), port)
+            .await
+    }
+
+    /// Get channel graph known to the node
+    async fn get_network_graph(&self) -> Result<NetworkGraph>;
+
+    /// List peers
+    async fn list_peers(&self) -> Result<Vec<PeerInfo>>;
+
     /// Open channel to peer
+    ///
+    /// Channels opened with `announce` false are private. `None` channel type lets the
+    /// nodes negotiate it. `minconf` is the minimum confirmations of the funding
+    /// utxos, `None` uses the node default
     async fn open_channel(
         &self,
         amount_sat: u64,
         peer_id: &str,
         push_amount: Option<u64>,
+        announce: bool,
+        channel_type: Option<ChannelType>,
+        minconf: Option<u32>,
     ) -> Result<()>;
 
+    /// Close channel
+    ///
+    /// Force closes unilaterally when `force` is set
+    async fn close_channel(&self, channel_id: &str, force: bool) -> Result<()>;
+
     /// Balance
     async fn balance(&self) -> Result<Balance>;
 
+    /// Error with [`RegtestError::AssertionFailed`] if spendable on chain balance is below min
+    async fn assert_min_onchain_balance(&self, min_sat: u64) -> Result<()> {
+        let balance = self.balance().await?;
+
+        let on_chain_spendable_sat = balance.on_chain_spendable / 1000;
+
+        if on_chain_spendable_sat < min_sat {
+            return Err(RegtestError::AssertionFailed {
+                expected: min_sat,
+                actual: on_chain_spendable_sat,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Error with [`RegtestError::AssertionFailed`] if lightning balance is below min
+    async fn assert_min_ln_balance(&self, min_msat: u64) -> Result<()> {
+        let balance = self.balance().await?;
+
+        if balance.ln < min_msat {
+            return Err(RegtestError::AssertionFailed {
+                expected: min_msat,
+                actual: balance.ln,
+            });
+        }
+
+        Ok(())
+    }
+
     /// Pa bolt11 invoice
-    async fn pay_invoice(&self, bolt11: String) -> Result<String>;
+    ///
+    /// Fee is not limited if `max_fee_msat` is not set
+    async fn pay_invoice(&self, bolt11: String, max_fee_msat: Option<u64>) -> Result<String>;
+
+    /// Pay bolt11 invoice without an amount
+    async fn pay_invoice_with_amount(
+        &self,
+        bolt11: String,
+        amount_msat: u64,
+        max_fee_msat: Option<u64>,
+    ) -> Result<String>;
+
+    /// Pay bolt11 invoice expecting the payment to fail
+    ///
+    /// A `max_fee_msat` of zero makes payments routed through another node fail.
+    /// Returns the payment error, a successful payment is an error
+    async fn pay_invoice_expecting_failure(
+        &self,
+        bolt11: &str,
+        max_fee_msat: Option<u64>,
+    ) -> Result<RegtestError> {
+        match self.pay_invoice(bolt11.to_string(), max_fee_msat).await {
+            Ok(_) => bail!("Unexpected success paying invoice"),
+            Err(err) => Ok(err),
+        }
+    }
+
+    /// Send spontaneous payment to node
+    ///
+    /// Returns the payment preimage
+    async fn keysend(&self, node_pubkey: &str, amount_msat: u64) -> Result<String>;
+
+    /// Get route to destination without paying
+    async fn get_route(&self, destination: &str, amount_msat: u64) -> Result<Vec<RouteHop>>;
+
+    /// Get state of a single channel
+    async fn get_channel_info(&self, channel_id: &str) -> Result<ChannelInfo>;
+
+    /// List channels that are not in normal operation, opening or closing
+    async fn get_pending_channels(&self) -> Result<Vec<PendingChannelInfo>>;
+
+    /// Update routing fees and cltv delta of channel
+    async fn update_channel_policy(
+        &self,
+        short_channel_id: &str,
+        base_fee_msat: u64,
+        fee_ppm: u64,
+        cltv_delta: u32,
+    ) -> Result<()>;
+
+    /// Pay ourselves out through `out_channel` and back in through `in_channel`
+    ///
+    /// Returns the payment preimage
+    async fn rebalance_channel(
+        &self,
+        out_channel: &str,
+        in_channel: &str,
+        amount_msat: u64,
+    ) -> Result<String>;
 
-    /// Create bolt11 invoice    
-    async fn create_invoice(&self, amount_sat: Option<u64>) -> Result<String>;
+    /// Create bolt11 invoice
+    ///
+    /// `None` expiry uses the node default
+    async fn create_invoice(
+        &self,
+        amount_msat: Option<u64>,
+        expiry_secs: Option<u64>,
+    ) -> Result<String>;
+
+    /// Decode bolt11 invoice without paying it
+    async fn decode_invoice(&self, bolt11: &str) -> Result<DecodedInvoice>;
 
     /// Wait for all channel to be active
-    async fn wait_channels_active(&self) -> Result<()>;
+    async fn wait_channels_active(&self, wait_config: Option<WaitConfig>) -> Result<()>;
 
     /// Wait for chain sync
-    async fn wait_chain_sync(&self) -> Result<()>;
+    async fn wait_chain_sync(&self, wait_config: Option<WaitConfig>) -> Result<()>;
 
     /// Check incoming invoice status
     async fn check_incoming_payment_status(&self, payment_hash: &str) -> Result<InvoiceStatus>;
 
     /// Check outgoing invoice status
     async fn check_outgoing_payment_status(&self, payment_hash: &str) -> Result<InvoiceStatus>;
+
+    /// List invoices in creation order, skipping the first `offset`
+    async fn list_invoices(
+        &self,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<Vec<InvoiceRecord>>;
+
+    /// List settled forwards between unix timestamps `start_time` and `end_time`
+    async fn get_forwarding_history(
+        &self,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<Vec<ForwardingEvent>>;
+
+    /// List outgoing payments, only completed ones unless `include_incomplete`
+    async fn list_payments(&self, include_incomplete: bool) -> Result<Vec<PaymentRecord>>;
+
+    /// Wait for outgoing payment to be paid or failed
+    async fn wait_for_payment(
+        &self,
+        payment_hash: &str,
+        timeout: Duration,
+    ) -> Result<InvoiceStatus> {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            // A payment that is not registered yet is not found
+            match self.check_outgoing_payment_status(payment_hash).await {
+                Ok(status @ (InvoiceStatus::Paid | InvoiceStatus::Failed)) => return Ok(status),
+                Ok(_) | Err(RegtestError::NotFound) => sleep(Duration::from_millis(500)).await,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    /// Wait for incoming hold invoice to be accepted
+    async fn wait_for_invoice_accepted(&self, payment_hash: &str, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            if self.check_incoming_payment_status(payment_hash).await? == InvoiceStatus::Accepted {
+                return Ok(());
+            }
+
+            sleep(Duration::from_millis(500)).await;
+        }
+
+        Err(RegtestError::Timeout)
+    }
+
+    /// Wait for incoming invoice to be paid
+    async fn wait_for_invoice_paid(&self, payment_hash: &str, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            if self.check_incoming_payment_status(payment_hash).await? == InvoiceStatus::Paid {
+                return Ok(());
+            }
+
+            sleep(Duration::from_millis(500)).await;
+        }
+
+        Err(RegtestError::Timeout)
+    }
+}
+
+/// Parse `major.minor.patch` from a node version string
+///
+/// Accepts a leading `v` and ignores any suffix such as `-beta` or `-modded`.
+/// A missing patch version is treated as `0`.
+pub(crate) fn parse_version(version: &str) -> Result<(u32, u32, u32)> {
+    let version = version.trim_start_matches('v');
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+
+    let mut parts = version[..end].split('.').map(|p| p.parse::<u32>());
+
+    let major = match parts.next() {
+        Some(Ok(major)) => major,
+        _ => bail!("Invalid version: {}", version),
+    };
+    let minor = match parts.next() {
+        Some(Ok(minor)) => minor,
+        _ => bail!("Invalid version: {}", version),
+    };
+    let patch = match parts.next() {
+        Some(Ok(patch)) => patch,
+        Some(Err(_)) => bail!("Invalid version: {}", version),
+        None => 0,
+    };
+
+    Ok((major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("0.17.0-beta").unwrap(), (0, 17, 0));
+        assert_eq!(
+            parse_version("0.18.3-beta commit=v0.18.3-beta").unwrap(),
+            (0, 18, 3)
+        );
+        assert_eq!(parse_version("v23.11.2").unwrap(), (23, 11, 2));
+        assert_eq!(parse_version("v24.05").unwrap(), (24, 5, 0));
+        assert_eq!(parse_version("v24.02.2-modded").unwrap(), (24, 2, 2));
+        assert!(parse_version("beta").is_err());
+    }
 }
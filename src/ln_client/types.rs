@@ -1,15 +1,433 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use futures_util::future::BoxFuture;
+
+use crate::InvoiceStatus;
+
 /// Connect Info
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
 pub struct ConnectInfo {
     pub pubkey: String,
     pub address: String,
     pub port: u16,
+    pub alias: Option<String>,
 }
 
 /// Balance response
+///
+/// All amounts are in msat
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
 pub struct Balance {
     pub on_chain_spendable: u64,
     pub on_chain_total: u64,
     pub ln: u64,
 }
+
+/// Snapshot of node state for debugging test failures
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
+pub struct NodeHealth {
+    pub chain_synced: bool,
+    /// CLN does not report gossip sync, it is true once lightningd caught up with bitcoind
+    pub graph_synced: bool,
+    pub active_channels: u32,
+    pub pending_channels: u32,
+    pub on_chain_balance_sat: u64,
+    pub ln_balance_msat: u64,
+}
+
+/// Watch only account
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct WatchOnlyAccount {
+    pub name: String,
+    pub address_type: u32,
+    pub extended_pubkey: String,
+    pub master_key_fingerprint: String,
+}
+
+/// Filter for listing channels from the gossip graph
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct ListChannelsFilter {
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub scid: Option<String>,
+}
+
+/// Closed channel transaction details
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct CloseTransaction {
+    pub txid: String,
+    pub close_type: String,
+    pub final_to_us_msat: u64,
+    pub min_possible_feerate_per_kw: Option<u32>,
+    pub max_possible_feerate_per_kw: Option<u32>,
+}
+
+/// Polling config for wait functions
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct WaitConfig {
+    pub poll_interval: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for WaitConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            max_attempts: 100,
+        }
+    }
+}
+
+/// State of a single channel
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct ChannelInfo {
+    pub short_channel_id: String,
+    pub capacity_sat: u64,
+    pub local_balance_sat: u64,
+    pub remote_balance_sat: u64,
+    pub active: bool,
+    /// Channel is not announced to the network
+    pub private: bool,
+}
+
+/// Onchain address type
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum AddressType {
+    P2wpkh,
+    P2shP2wpkh,
+    P2tr,
+}
+
+/// Commitment format of a new channel
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum ChannelType {
+    LegacyStaticKey,
+    AnchorOutputs,
+    AnchorZeroFeeHtlcTx,
+}
+
+/// Channel that is not yet open or is closing
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct PendingChannelInfo {
+    /// Funding outpoint `txid:vout`
+    pub channel_id: String,
+    pub capacity_sat: u64,
+    pub local_balance_sat: u64,
+    /// Confirmations still needed by a channel waiting to open, when known
+    pub blocks_until_open: Option<u32>,
+}
+
+/// Channel statistics
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct ChannelStats {
+    pub channel_id: String,
+    pub peer_id: String,
+    pub age_blocks: u32,
+    pub in_payments_offered: u64,
+    pub in_payments_fulfilled: u64,
+    pub in_msatoshi_fulfilled: u64,
+    pub out_payments_offered: u64,
+    pub out_payments_fulfilled: u64,
+    pub fee_income_msat: u64,
+}
+
+/// Peer info
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct PeerInfo {
+    pub pubkey: String,
+    pub connected: bool,
+    pub channels: u32,
+}
+
+/// Paid invoice info
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct InvoiceInfo {
+    pub label: String,
+    pub payment_hash: String,
+    pub bolt11: Option<String>,
+    pub amount_received_msat: Option<u64>,
+    pub pay_index: Option<u64>,
+}
+
+/// Invoice created by the node
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct InvoiceRecord {
+    pub payment_hash: String,
+    pub amount_msat: Option<u64>,
+    pub status: InvoiceStatus,
+    pub label: Option<String>,
+    /// Unix timestamp
+    pub created_at: u64,
+}
+
+/// Outgoing payment made by the node
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PaymentRecord {
+    pub payment_hash: String,
+    pub payment_preimage: Option<String>,
+    /// Amount including fees
+    pub amount_sent_msat: u64,
+    pub fee_msat: u64,
+    pub status: InvoiceStatus,
+}
+
+/// Fields of a decoded bolt11 invoice
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct DecodedInvoice {
+    pub payment_hash: String,
+    pub amount_msat: Option<u64>,
+    pub description: Option<String>,
+    pub expiry_secs: u64,
+    /// Unix timestamp
+    pub timestamp: u64,
+    pub payee_pubkey: Option<String>,
+}
+
+/// Settled htlc forwarded by the node
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct ForwardingEvent {
+    pub chan_id_in: String,
+    pub chan_id_out: String,
+    pub amt_in_msat: u64,
+    pub amt_out_msat: u64,
+    pub fee_msat: u64,
+    /// Unix timestamp
+    pub timestamp: u64,
+}
+
+/// Node known from the channel graph
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct NodeInfo {
+    pub pubkey: String,
+    pub alias: String,
+}
+
+/// Forwarding policy of one direction of a channel
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct ChannelPolicy {
+    pub base_fee_msat: u64,
+    pub fee_rate_ppm: u64,
+    pub cltv_delta: u32,
+    pub min_htlc_msat: u64,
+    pub disabled: bool,
+}
+
+/// Channel known from the channel graph
+///
+/// `node1_pubkey` is the lexicographically smaller key
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct ChannelEdge {
+    pub channel_id: String,
+    pub node1_pubkey: String,
+    pub node2_pubkey: String,
+    pub capacity_sat: u64,
+    pub node1_policy: Option<ChannelPolicy>,
+    pub node2_policy: Option<ChannelPolicy>,
+}
+
+/// Channel graph known to a node
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct NetworkGraph {
+    pub nodes: Vec<NodeInfo>,
+    pub channels: Vec<ChannelEdge>,
+}
+
+/// Invoice state change
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct InvoiceUpdate {
+    pub payment_hash: String,
+    pub status: InvoiceStatus,
+    pub amount_paid_msat: u64,
+}
+
+/// Incoming channel open waiting for a decision
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct ChannelOpenRequest {
+    pub node_pubkey: String,
+    pub pending_chan_id: String,
+    pub funding_amt_sat: u64,
+    pub push_amt_msat: u64,
+}
+
+/// Decision on an incoming channel open
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum ChannelOpenDecision {
+    Accept,
+    /// Reject with error sent to the peer
+    Reject(String),
+}
+
+/// Policy deciding on incoming channel opens
+///
+/// Default policy accepts all channels
+#[derive(Clone)]
+pub struct ChannelAcceptorPolicy(
+    Arc<dyn Fn(ChannelOpenRequest) -> BoxFuture<'static, ChannelOpenDecision> + Send + Sync>,
+);
+
+impl ChannelAcceptorPolicy {
+    /// Create policy from async closure
+    pub fn new<F, Fut>(policy: F) -> Self
+    where
+        F: Fn(ChannelOpenRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ChannelOpenDecision> + Send + 'static,
+    {
+        Self(Arc::new(move |request| Box::pin(policy(request))))
+    }
+
+    /// Decide on channel open
+    pub async fn decide(&self, request: ChannelOpenRequest) -> ChannelOpenDecision {
+        (self.0)(request).await
+    }
+}
+
+impl Default for ChannelAcceptorPolicy {
+    fn default() -> Self {
+        Self::new(|_request| async { ChannelOpenDecision::Accept })
+    }
+}
+
+/// On chain wallet transaction
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct OnChainTx {
+    pub txid: String,
+    pub block_height: Option<u32>,
+    /// Net change of the wallet balance
+    pub amount_msat: i64,
+    pub confirms: u32,
+}
+
+/// Unspent output of the on chain wallet
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct Utxo {
+    pub txid: String,
+    pub vout: u32,
+    pub amount_sat: u64,
+    pub address: String,
+    pub confirmations: u32,
+    /// Confirmed and not reserved by the node
+    pub spendable: bool,
+}
+
+/// Kind of htlc event
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum HtlcEventType {
+    Forward,
+    ForwardFail,
+    Settle,
+    Link,
+}
+
+/// Htlc lifecycle event
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct HtlcEvent {
+    pub incoming_channel_id: u64,
+    pub outgoing_channel_id: u64,
+    pub event_type: HtlcEventType,
+    pub payment_hash: Option<String>,
+}
+
+/// Hop of a route hint
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct HopHint {
+    pub node_id: String,
+    pub chan_id: u64,
+    pub fee_base_msat: u32,
+    pub fee_proportional_millionths: u32,
+    pub cltv_expiry_delta: u32,
+}
+
+/// Route hint to a private channel
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct RouteHint {
+    pub hop_hints: Vec<HopHint>,
+}
+
+/// Hop of a payment route
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct RouteHop {
+    pub pubkey: String,
+    pub short_channel_id: String,
+    pub fee_msat: u64,
+    pub cltv_expiry_delta: u32,
+}
+
+/// Htlc in flight on a channel
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct HtlcInfo {
+    pub id: u64,
+    pub payment_hash: String,
+    pub amount_msat: u64,
+    pub expiry: u32,
+}
+
+/// Htlcs in flight on a channel
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct ChannelHtlcState {
+    pub channel_id: String,
+    pub offered_htlcs: Vec<HtlcInfo>,
+    pub received_htlcs: Vec<HtlcInfo>,
+}
+
+/// Htlcs in flight across all channels at `timestamp` (unix seconds)
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct HtlcSnapshot {
+    pub timestamp: u64,
+    pub channels: Vec<ChannelHtlcState>,
+}
+
+/// Htlcs added and removed between two snapshots, grouped by channel
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
+pub struct HtlcDiff {
+    pub added: Vec<ChannelHtlcState>,
+    pub removed: Vec<ChannelHtlcState>,
+}
+
+/// Htlcs present in `b` but not `a` are added, those only in `a` are removed
+pub fn diff(a: &HtlcSnapshot, b: &HtlcSnapshot) -> HtlcDiff {
+    HtlcDiff {
+        added: htlcs_missing_from(b, a),
+        removed: htlcs_missing_from(a, b),
+    }
+}
+
+/// Htlcs of `snapshot` that are not in `other`
+fn htlcs_missing_from(snapshot: &HtlcSnapshot, other: &HtlcSnapshot) -> Vec<ChannelHtlcState> {
+    snapshot
+        .channels
+        .iter()
+        .filter_map(|channel| {
+            let other_channel = other
+                .channels
+                .iter()
+                .find(|c| c.channel_id == channel.channel_id);
+
+            let missing = |htlcs: &[HtlcInfo], other_htlcs: Option<&Vec<HtlcInfo>>| {
+                htlcs
+                    .iter()
+                    .filter(|htlc| !other_htlcs.is_some_and(|o| o.iter().any(|h| h.id == htlc.id)))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+
+            let offered_htlcs = missing(
+                &channel.offered_htlcs,
+                other_channel.map(|c| &c.offered_htlcs),
+            );
+            let received_htlcs = missing(
+                &channel.received_htlcs,
+                other_channel.map(|c| &c.received_htlcs),
+            );
+
+            if offered_htlcs.is_empty() && received_htlcs.is_empty() {
+                return None;
+            }
+
+            Some(ChannelHtlcState {
+                channel_id: channel.channel_id.clone(),
+                offered_htlcs,
+                received_htlcs,
+            })
+        })
+        .collect()
+}
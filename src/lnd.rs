@@ -1,13 +1,15 @@
 //! LND
 
 use std::{
+    fs::File,
     path::PathBuf,
     process::{Child, Command, Stdio},
-    thread::sleep,
     time::Duration,
 };
 
-use anyhow::{anyhow, bail, Result};
+use tokio::time::sleep;
+
+use crate::{bail, RegtestError, Result};
 
 /// Lnd
 pub struct Lnd {
@@ -17,13 +19,18 @@ pub struct Lnd {
     rpc_listen: String,
     bitcoin_rpc_user: String,
     bitcoin_rpc_password: String,
+    bitcoin_rpc_port: u16,
     child: Option<Child>,
     zmq_raw_block: String,
     zmq_raw_tx: String,
+    log_file: Option<PathBuf>,
+    log_level: Option<String>,
+    dev_mode: bool,
 }
 
 impl Lnd {
     /// Create new [`Lnd`]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         bitcoin_data_dir: PathBuf,
         data_dir: PathBuf,
@@ -31,8 +38,12 @@ impl Lnd {
         rpc_listen: String,
         bitcoin_rpc_user: String,
         bitcoin_rpc_password: String,
+        bitcoin_rpc_port: u16,
         zmq_raw_block: String,
         zmq_raw_tx: String,
+        log_file: Option<PathBuf>,
+        log_level: Option<String>,
+        dev_mode: bool,
     ) -> Self {
         Self {
             data_dir,
@@ -41,14 +52,18 @@ impl Lnd {
             rpc_listen,
             bitcoin_rpc_user,
             bitcoin_rpc_password,
+            bitcoin_rpc_port,
             child: None,
             zmq_raw_block,
             zmq_raw_tx,
+            log_file,
+            log_level,
+            dev_mode,
         }
     }
 
     /// Start lnd
-    pub fn start_lnd(&mut self) -> Result<()> {
+    pub async fn start_lnd(&mut self) -> Result<()> {
         let mut cmd = Command::new("lnd");
         cmd.arg("--bitcoin.active");
         cmd.arg("--bitcoin.regtest");
@@ -65,6 +80,10 @@ impl Lnd {
         ));
         cmd.arg(format!("--bitcoind.rpcuser={}", self.bitcoin_rpc_user));
         cmd.arg(format!("--bitcoind.rpcpass={}", self.bitcoin_rpc_password));
+        cmd.arg(format!(
+            "--bitcoind.rpchost=127.0.0.1:{}",
+            self.bitcoin_rpc_port
+        ));
         cmd.arg(format!("--rpclisten={}", self.rpc_listen));
         cmd.arg("--norest");
         cmd.arg(format!("--lnddir={}", self.data_dir.display()));
@@ -75,21 +94,50 @@ impl Lnd {
         cmd.arg(format!("--externalip={}", self.addr.to_string_lossy()));
         //        panic!("{}", self.addr.to_string_lossy());
 
-        // Send output to dev null
-        cmd.stdout(Stdio::null());
+        if let Some(log_level) = &self.log_level {
+            cmd.arg(format!("--debuglevel={}", log_level));
+        }
+
+        // Only accepted by lnd built with the `dev` build tag
+        if self.dev_mode {
+            cmd.arg("--dev");
+        }
+
+        match &self.log_file {
+            Some(log_file) => {
+                let log = File::create(log_file)?;
+                cmd.stdout(log.try_clone()?);
+                cmd.stderr(log);
+            }
+            None => {
+                // Send output to dev null
+                cmd.stdout(Stdio::null());
+            }
+        }
 
-        let child = cmd.spawn()?;
+        let child = cmd.spawn().map_err(RegtestError::ProcessSpawnError)?;
 
         self.child = Some(child);
 
         // Let clnd start up
-        sleep(Duration::from_secs(10));
+        sleep(Duration::from_secs(10)).await;
 
         Ok(())
     }
 
+    /// Path to lnd tls certificate
+    pub fn tls_cert_path(&self) -> PathBuf {
+        self.data_dir.join("tls.cert")
+    }
+
+    /// Path to lnd admin macaroon
+    pub fn admin_macaroon_path(&self) -> PathBuf {
+        self.data_dir
+            .join("data/chain/bitcoin/regtest/admin.macaroon")
+    }
+
     pub fn pid(&self) -> Result<u32> {
-        let child = self.child.as_ref().ok_or(anyhow!("Unknow child"))?;
+        let child = self.child.as_ref().ok_or(RegtestError::NotFound)?;
 
         Ok(child.id())
     }
@@ -119,7 +167,7 @@ impl Lnd {
         cmd.arg("--tlscertpath");
         cmd.arg(tls_cert_path);
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd.spawn().map_err(RegtestError::ProcessSpawnError)?;
 
         child.wait()?;
 
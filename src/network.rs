@@ -0,0 +1,326 @@
+//! Regtest network
+
+use std::{net::TcpListener, path::PathBuf, str::FromStr};
+
+use tempfile::{tempdir, TempDir};
+
+use crate::{
+    bitcoin_client::BitcoinClient,
+    bitcoind::Bitcoind,
+    cln::Clnd,
+    ln_client::{ClnClient, LightningClient, LndClient},
+    lnd::Lnd,
+    RegtestError, Result,
+};
+
+/// Regtest network of CLN nodes
+pub struct RegtestNetwork {
+    alice: ClnClient,
+    hub: ClnClient,
+    bob: ClnClient,
+    _env: MultiNodeEnv,
+}
+
+impl RegtestNetwork {
+    /// Create Alice -> Hub -> Bob topology
+    ///
+    /// `bitcoin_client` must have a funded wallet, it is used to fund the nodes and mine blocks
+    pub async fn with_routing_node(
+        bitcoind: &Bitcoind,
+        bitcoin_client: &BitcoinClient,
+    ) -> Result<Self> {
+        let mut env = MultiNodeEnv::new(bitcoind)?;
+
+        for addr in free_local_addrs(3)? {
+            env.add_cln_node(ClnConfig {
+                addr,
+                ..Default::default()
+            });
+        }
+        env.start_all().await?;
+
+        let node = |idx| {
+            env.get_cln_client(idx)
+                .cloned()
+                .ok_or(RegtestError::NotFound)
+        };
+        let (alice, hub, bob) = (node(0)?, node(1)?, node(2)?);
+
+        for node in [&alice, &hub, &bob] {
+            let address = node.get_new_onchain_address(None).await?;
+            bitcoin_client.send_to_address(&address, 5_000_000)?;
+        }
+
+        let mine_to_address = bitcoin_client.get_new_address()?;
+        bitcoin_client.generate_blocks(&mine_to_address, 100)?;
+
+        for node in [&alice, &hub, &bob] {
+            node.wait_chain_sync(None).await?;
+        }
+
+        let hub_info = hub.get_connect_info().await?;
+        let bob_info = bob.get_connect_info().await?;
+
+        alice
+            .connect_peer(
+                hub_info.pubkey.clone(),
+                hub_info.address.clone(),
+                hub_info.port,
+            )
+            .await?;
+        bob.connect_peer(hub_info.pubkey.clone(), hub_info.address, hub_info.port)
+            .await?;
+
+        alice
+            .open_channel(
+                2_000_000,
+                &hub_info.pubkey,
+                Some(1_000_000),
+                true,
+                None,
+                None,
+            )
+            .await?;
+        hub.open_channel(
+            2_000_000,
+            &bob_info.pubkey,
+            Some(1_000_000),
+            true,
+            None,
+            None,
+        )
+        .await?;
+
+        bitcoin_client.generate_blocks(&mine_to_address, 6)?;
+
+        for node in [&alice, &hub, &bob] {
+            node.wait_chain_sync(None).await?;
+            node.wait_channels_active(None).await?;
+        }
+
+        Ok(Self {
+            alice,
+            hub,
+            bob,
+            _env: env,
+        })
+    }
+
+    /// Alice edge node
+    pub fn alice(&self) -> &ClnClient {
+        &self.alice
+    }
+
+    /// Routing node
+    pub fn hub(&self) -> &ClnClient {
+        &self.hub
+    }
+
+    /// Bob edge node
+    pub fn bob(&self) -> &ClnClient {
+        &self.bob
+    }
+}
+
+/// CLN node of a [`MultiNodeEnv`]
+#[derive(Debug, Clone, Default)]
+pub struct ClnConfig {
+    /// Address the node listens on
+    pub addr: String,
+    pub max_concurrent_htlcs: Option<u32>,
+    pub plugins: Vec<PathBuf>,
+    pub log_level: Option<String>,
+}
+
+/// LND node of a [`MultiNodeEnv`]
+#[derive(Debug, Clone, Default)]
+pub struct LndConfig {
+    /// Address the node listens on
+    pub addr: String,
+    /// Grpc address
+    pub rpc_listen: String,
+    pub log_file: Option<PathBuf>,
+    pub log_level: Option<String>,
+}
+
+/// Any number of CLN and LND nodes on a running bitcoind
+///
+/// Nodes are indexed per implementation in the order they were added
+pub struct MultiNodeEnv {
+    bitcoin_data_dir: PathBuf,
+    rpc_user: String,
+    rpc_password: String,
+    rpc_port: u16,
+    zmq_raw_block: String,
+    zmq_raw_tx: String,
+    cln_configs: Vec<ClnConfig>,
+    lnd_configs: Vec<LndConfig>,
+    cln_clients: Vec<ClnClient>,
+    lnd_clients: Vec<LndClient>,
+    clnds: Vec<Clnd>,
+    lnds: Vec<Lnd>,
+    temp_dir: TempDir,
+}
+
+impl MultiNodeEnv {
+    /// Create new [`MultiNodeEnv`] using `bitcoind` as chain backend
+    pub fn new(bitcoind: &Bitcoind) -> Result<Self> {
+        Ok(Self {
+            bitcoin_data_dir: bitcoind.data_dir().clone(),
+            rpc_user: bitcoind.rpc_user().to_string(),
+            rpc_password: bitcoind.rpc_password().to_string(),
+            rpc_port: bitcoind.rpc_port(),
+            zmq_raw_block: bitcoind.zmq_block_addr().to_string(),
+            zmq_raw_tx: bitcoind.zmq_tx_addr().to_string(),
+            cln_configs: vec![],
+            lnd_configs: vec![],
+            cln_clients: vec![],
+            lnd_clients: vec![],
+            clnds: vec![],
+            lnds: vec![],
+            temp_dir: tempdir()?,
+        })
+    }
+
+    /// Add CLN node, started by [`MultiNodeEnv::start_all`]
+    pub fn add_cln_node(&mut self, config: ClnConfig) -> &mut Self {
+        self.cln_configs.push(config);
+        self
+    }
+
+    /// Add LND node, started by [`MultiNodeEnv::start_all`]
+    pub fn add_lnd_node(&mut self, config: LndConfig) -> &mut Self {
+        self.lnd_configs.push(config);
+        self
+    }
+
+    /// Start all nodes not yet running and wait for them to sync to chain
+    pub async fn start_all(&mut self) -> Result<()> {
+        for (idx, config) in self.cln_configs.iter().enumerate().skip(self.clnds.len()) {
+            let data_dir = self.temp_dir.path().join(format!("cln_{}", idx));
+            std::fs::create_dir_all(&data_dir)?;
+
+            let mut clnd = Clnd::new(
+                self.bitcoin_data_dir.clone(),
+                data_dir.clone(),
+                PathBuf::from_str(&config.addr)?,
+                self.rpc_user.clone(),
+                self.rpc_password.clone(),
+                self.rpc_port,
+                config.max_concurrent_htlcs,
+                config.plugins.clone(),
+                config.log_level.clone(),
+            );
+            clnd.start_clnd()?;
+
+            // Process and client are kept together so indexes stay aligned,
+            // a failed start drops and stops the process
+            let client = ClnClient::new(data_dir, None).await?;
+            client.wait_chain_sync(None).await?;
+            self.clnds.push(clnd);
+            self.cln_clients.push(client);
+
+            tracing::info!("Started cln node {}", idx);
+        }
+
+        for (idx, config) in self.lnd_configs.iter().enumerate().skip(self.lnds.len()) {
+            let data_dir = self.temp_dir.path().join(format!("lnd_{}", idx));
+            std::fs::create_dir_all(&data_dir)?;
+
+            let mut lnd = Lnd::new(
+                self.bitcoin_data_dir.clone(),
+                data_dir.clone(),
+                PathBuf::from_str(&config.addr)?,
+                config.rpc_listen.clone(),
+                self.rpc_user.clone(),
+                self.rpc_password.clone(),
+                self.rpc_port,
+                self.zmq_raw_block.clone(),
+                self.zmq_raw_tx.clone(),
+                config.log_file.clone(),
+                config.log_level.clone(),
+                false,
+            );
+            lnd.start_lnd().await?;
+
+            let client = LndClient::new(
+                format!("https://{}", config.rpc_listen),
+                lnd.tls_cert_path(),
+                lnd.admin_macaroon_path(),
+            )
+            .await?;
+            client.wait_chain_sync(None).await?;
+            self.lnds.push(lnd);
+            self.lnd_clients.push(client);
+
+            tracing::info!("Started lnd node {}", idx);
+        }
+
+        Ok(())
+    }
+
+    /// Stop all running nodes, they can be started again with [`MultiNodeEnv::start_all`]
+    ///
+    /// Every node is stopped even if stopping one fails, the first error is returned
+    pub async fn stop_all(&mut self) -> Result<()> {
+        self.cln_clients.clear();
+        self.lnd_clients.clear();
+
+        let mut result = Ok(());
+
+        for mut lnd in self.lnds.drain(..) {
+            if let Err(err) = lnd.stop_lnd() {
+                tracing::error!("Could not stop lnd: {}", err);
+                result = result.and(Err(err));
+            }
+        }
+
+        for mut clnd in self.clnds.drain(..) {
+            if let Err(err) = clnd.stop_clnd() {
+                tracing::error!("Could not stop clnd: {}", err);
+                result = result.and(Err(err));
+            }
+        }
+
+        result
+    }
+
+    /// Client of the `idx`th CLN node, `None` if the node has not been started
+    pub fn get_cln_client(&self, idx: usize) -> Option<&ClnClient> {
+        self.cln_clients.get(idx)
+    }
+
+    /// Client of the `idx`th LND node, `None` if the node has not been started
+    pub fn get_lnd_client(&self, idx: usize) -> Option<&LndClient> {
+        self.lnd_clients.get(idx)
+    }
+}
+
+/// Local addresses on distinct free ports
+fn free_local_addrs(count: usize) -> Result<Vec<String>> {
+    // Listeners are held until all ports are picked so they differ
+    let listeners = (0..count)
+        .map(|_| TcpListener::bind("127.0.0.1:0"))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    listeners
+        .iter()
+        .map(|listener| Ok(listener.local_addr()?.to_string()))
+        .collect()
+}
+
+/// Connect every node to every other node so gossip propagates between them
+pub async fn bootstrap_gossip_from(nodes: &[&dyn LightningClient]) -> Result<()> {
+    for (i, node) in nodes.iter().enumerate() {
+        for peer in nodes.iter().skip(i + 1) {
+            let peer_info = peer.get_connect_info().await?;
+
+            node.connect_peer(peer_info.pubkey, peer_info.address, peer_info.port)
+                .await?;
+        }
+    }
+
+    tracing::info!("Bootstrapped gossip between {} nodes", nodes.len());
+
+    Ok(())
+}